@@ -1,53 +1,219 @@
 use ahash::AHashMap;
+use std::fmt;
 
-fn count(cache: &mut AHashMap<(i64, i64), i64>, number: i64, blinks: i64) -> i64 {
-    if let Some(&cached_result) = cache.get(&(number, blinks)) {
-        return cached_result;
+// Stones start below 2^64, so an odd-digit stone has at most 19 digits when
+// it is first multiplied. Multiplying a stone whose leading digits are at
+// least 494 adds four digits but leaves the leading digit at 1 or 2, so the
+// next product only adds three and gets split. No stone ever reaches 27 digits,
+// which keeps every stone well inside a u128.
+fn blink(number: u128) -> (u128, Option<u128>) {
+    if number == 0 {
+        return (1, None);
     }
-
-    let result = if blinks == 0 {
-        1
-    } else if number == 0 {
-        count(cache, 1, blinks - 1)
+    let digits = number.ilog10() + 1;
+    if digits.is_multiple_of(2) {
+        let div = 10u128.pow(digits / 2);
+        (number / div, Some(number % div))
     } else {
-        let digits = count_digits(number);
-        if digits % 2 == 0 {
-            let div = 10i64.pow(digits as u32 / 2);
-            count(cache, number / div, blinks - 1) + count(cache, number % div, blinks - 1)
-        } else {
-            count(cache, number * 2024, blinks - 1)
+        (number * 2024, None)
+    }
+}
+
+fn count_with<T: Copy>(
+    stones: &[u64],
+    blinks: usize,
+    one: T,
+    add: impl Fn(T, T) -> Option<T>,
+) -> Option<T> {
+    let mut counts: AHashMap<u128, T> = AHashMap::new();
+    for &stone in stones {
+        let value = match counts.get(&(stone as u128)) {
+            Some(&c) => add(c, one)?,
+            None => one,
+        };
+        counts.insert(stone as u128, value);
+    }
+
+    for _ in 0..blinks {
+        let mut next: AHashMap<u128, T> = AHashMap::with_capacity(counts.len() * 2);
+        for (&number, &c) in &counts {
+            let (left, right) = blink(number);
+            for stone in std::iter::once(left).chain(right) {
+                let value = match next.get(&stone) {
+                    Some(&existing) => add(existing, c)?,
+                    None => c,
+                };
+                next.insert(stone, value);
+            }
+        }
+        counts = next;
+    }
+
+    let mut values = counts.into_values();
+    let first = values.next()?;
+    values.try_fold(first, add)
+}
+
+pub fn count(stones: &[u64], blinks: usize) -> Option<u128> {
+    if stones.is_empty() {
+        return Some(0);
+    }
+    count_with(stones, blinks, 1u128, |a, b| a.checked_add(b))
+}
+
+const MAX_STONES: usize = 1 << 20;
+const MAX_STEPPED_BLINKS: usize = 1 << 16;
+const MAX_MATRIX_STONES: usize = 128;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlinkError {
+    TooManyStones { limit: usize },
+    // More than MAX_STEPPED_BLINKS blinks over more than MAX_MATRIX_STONES
+    // distinct stones. Real puzzle inputs reach thousands of distinct stones,
+    // so for them this is hit just past MAX_STEPPED_BLINKS.
+    TooManyBlinks { blinks: usize, stones: usize },
+    ZeroModulus,
+}
+
+impl fmt::Display for BlinkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            BlinkError::TooManyStones { limit } => {
+                write!(f, "more than {} distinct stones", limit)
+            }
+            BlinkError::TooManyBlinks { blinks, stones } => write!(
+                f,
+                "{} blinks over {} distinct stones is beyond {} blinks and {} stones",
+                blinks, stones, MAX_STEPPED_BLINKS, MAX_MATRIX_STONES
+            ),
+            BlinkError::ZeroModulus => write!(f, "modulus must be positive"),
         }
+    }
+}
+
+impl std::error::Error for BlinkError {}
+
+// Ids of the starting stones, and for every stone that can ever appear, the
+// ids of the stones it turns into after a blink.
+type Transitions = (Vec<usize>, Vec<(usize, Option<usize>)>);
+
+fn transitions(stones: &[u64]) -> Result<Transitions, BlinkError> {
+    let mut ids: AHashMap<u128, usize> = AHashMap::new();
+    let mut queue = vec![];
+    let mut id = |stone: u128, queue: &mut Vec<u128>| {
+        let next = ids.len();
+        *ids.entry(stone).or_insert_with(|| {
+            queue.push(stone);
+            next
+        })
     };
-    cache.insert((number, blinks), result);
+    let starts = stones
+        .iter()
+        .map(|&stone| id(stone as u128, &mut queue))
+        .collect::<Vec<_>>();
+    let mut next = vec![];
+    while next.len() < queue.len() {
+        if queue.len() > MAX_STONES {
+            return Err(BlinkError::TooManyStones { limit: MAX_STONES });
+        }
+        let (left, right) = blink(queue[next.len()]);
+        let left = id(left, &mut queue);
+        let right = right.map(|right| id(right, &mut queue));
+        next.push((left, right));
+    }
+    Ok((starts, next))
+}
+
+fn multiply(a: &[Vec<u64>], b: &[Vec<u64>], modulus: u64) -> Vec<Vec<u64>> {
+    let mut result = vec![vec![0u64; b[0].len()]; a.len()];
+    for (row, a_row) in result.iter_mut().zip(a) {
+        for (&x, b_row) in a_row.iter().zip(b) {
+            if x == 0 {
+                continue;
+            }
+            for (cell, &y) in row.iter_mut().zip(b_row) {
+                *cell = ((*cell as u128 + x as u128 * y as u128) % modulus as u128) as u64;
+            }
+        }
+    }
     result
 }
 
-fn count_digits(number: i64) -> i64 {
-    let mut digits = 1;
-    let mut zeros = 10;
-    while zeros <= number {
-        digits += 1;
-        zeros *= 10;
+// Number of stones modulo `modulus`. Small blink counts are stepped through
+// one at a time; past MAX_STEPPED_BLINKS the blink is a fixed linear map on
+// the distinct stones, which is raised to the power of `blinks` as long as
+// there are at most MAX_MATRIX_STONES of them. That only holds for tiny inputs
+// like `125 17`: a typical eight-stone input grows to a few thousand distinct
+// stones, so beyond MAX_STEPPED_BLINKS it fails with TooManyBlinks.
+pub fn count_modulo(stones: &[u64], blinks: usize, modulus: u64) -> Result<u64, BlinkError> {
+    if modulus == 0 {
+        return Err(BlinkError::ZeroModulus);
     }
-    digits
+    let (starts, next) = transitions(stones)?;
+    let add = |a: u64, b: u64| ((a as u128 + b as u128) % modulus as u128) as u64;
+
+    if blinks <= MAX_STEPPED_BLINKS {
+        let mut counts = vec![0u64; next.len()];
+        for &start in &starts {
+            counts[start] = add(counts[start], 1);
+        }
+        for _ in 0..blinks {
+            let mut following = vec![0u64; next.len()];
+            for (&(left, right), &c) in next.iter().zip(&counts) {
+                following[left] = add(following[left], c);
+                if let Some(right) = right {
+                    following[right] = add(following[right], c);
+                }
+            }
+            counts = following;
+        }
+        return Ok(counts.into_iter().fold(0, add));
+    }
+
+    if next.len() > MAX_MATRIX_STONES {
+        return Err(BlinkError::TooManyBlinks {
+            blinks,
+            stones: next.len(),
+        });
+    }
+    let size = next.len();
+    let mut step = vec![vec![0u64; size]; size];
+    for (from, &(left, right)) in next.iter().enumerate() {
+        step[from][left] = add(step[from][left], 1);
+        if let Some(right) = right {
+            step[from][right] = add(step[from][right], 1);
+        }
+    }
+    let mut power = (0..size)
+        .map(|i| (0..size).map(|j| u64::from(i == j) % modulus).collect())
+        .collect::<Vec<Vec<u64>>>();
+    let mut remaining = blinks;
+    while remaining > 0 {
+        if remaining % 2 == 1 {
+            power = multiply(&power, &step, modulus);
+        }
+        step = multiply(&step, &step, modulus);
+        remaining /= 2;
+    }
+    Ok(starts
+        .iter()
+        .flat_map(|&start| power[start].iter().copied())
+        .fold(0, add))
 }
 
-pub fn part1(input: &str) -> i64 {
-    let mut cache: AHashMap<(i64, i64), i64> = AHashMap::new();
+pub fn parse(input: &str) -> Vec<u64> {
     input
-        .split(" ")
-        .map(|x| x.parse::<i64>().unwrap())
-        .map(|number| count(&mut cache, number, 25))
-        .sum()
+        .split_whitespace()
+        .map(|x| x.parse::<u64>().unwrap())
+        .collect()
+}
+
+pub fn part1(input: &str) -> i64 {
+    count(&parse(input), 25).unwrap() as i64
 }
 
 pub fn part2(input: &str) -> i64 {
-    let mut cache: AHashMap<(i64, i64), i64> = AHashMap::new();
-    input
-        .split(" ")
-        .map(|x| x.parse::<i64>().unwrap())
-        .map(|number| count(&mut cache, number, 75))
-        .sum()
+    count(&parse(input), 75).unwrap() as i64
 }
 
 #[cfg(test)]
@@ -65,4 +231,90 @@ mod tests {
     fn test_day11_part2() {
         assert_eq!(part2(INPUT), 65601038650482);
     }
+
+    #[test]
+    fn test_day11_overflow() {
+        let stones = parse(INPUT);
+        assert_eq!(count(&stones, 6), Some(22));
+        assert!(count(&stones, 150).is_some());
+        assert_eq!(count(&stones, 500), None);
+    }
+
+    #[test]
+    fn test_day11_modulo() {
+        let stones = parse(INPUT);
+        let modulus = 1_000_000_007;
+        assert_eq!(
+            count_modulo(&stones, 75, modulus),
+            Ok((65601038650482u128 % modulus as u128) as u64)
+        );
+        let exact = count(&stones, 150).unwrap();
+        assert_eq!(
+            count_modulo(&stones, 150, modulus),
+            Ok((exact % modulus as u128) as u64)
+        );
+        assert!(count_modulo(&stones, 500, modulus).is_ok());
+        assert_eq!(count_modulo(&stones, 10, 1), Ok(0));
+        assert_eq!(count_modulo(&[], 10, 7), Ok(0));
+    }
+
+    #[test]
+    fn test_day11_large_stones() {
+        // Odd digit counts that overflowed a u64 once multiplied by 2024.
+        let stones = [99_999_999_999_999_999, 9_999_999_999_999_999_999];
+        let exact = count(&stones, 40).unwrap();
+        assert_eq!(count_modulo(&stones, 40, 1 << 61), Ok(exact as u64));
+        assert_eq!(
+            count_modulo(&[u64::MAX], 100, 1_000_000_007).map(|_| ()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_day11_many_blinks() {
+        let modulus = 1_000_000_007;
+        let exact = count(&[0], 150).unwrap();
+        assert_eq!(
+            count_modulo(&[0], 150, modulus),
+            Ok((exact % modulus as u128) as u64)
+        );
+        // Past the stepping limit the closure of 0 is raised to a power, which
+        // must agree with stepping through the same blinks.
+        let blinks = MAX_STEPPED_BLINKS + 1;
+        let expected = {
+            let (starts, next) = transitions(&[0]).unwrap();
+            let mut counts = vec![0u64; next.len()];
+            counts[starts[0]] = 1;
+            for _ in 0..blinks {
+                let mut following = vec![0u64; next.len()];
+                for (&(left, right), &c) in next.iter().zip(&counts) {
+                    following[left] = (following[left] + c) % modulus;
+                    if let Some(right) = right {
+                        following[right] = (following[right] + c) % modulus;
+                    }
+                }
+                counts = following;
+            }
+            counts.into_iter().fold(0, |a, b| (a + b) % modulus)
+        };
+        assert_eq!(count_modulo(&[0], blinks, modulus), Ok(expected));
+        assert!(count_modulo(&[0], 1_000_000_000_000, modulus).is_ok());
+        assert!(count_modulo(&[125, 17], 1_000_000_000_000, modulus).is_ok());
+        let stones = [1234567, 987654321, 28, 9999];
+        assert_eq!(
+            count_modulo(&stones, 1_000_000_000_000, modulus),
+            Err(BlinkError::TooManyBlinks {
+                blinks: 1_000_000_000_000,
+                stones: transitions(&stones).unwrap().1.len()
+            })
+        );
+        assert!(count_modulo(&stones, 1000, modulus).is_ok());
+
+        let stones = [4022724, 951333, 0, 21633, 5857, 97, 702, 6];
+        assert!(matches!(
+            count_modulo(&stones, 70_000, modulus),
+            Err(BlinkError::TooManyBlinks { blinks: 70_000, stones }) if stones > MAX_MATRIX_STONES
+        ));
+        assert_eq!(count_modulo(&[0], 10, 0), Err(BlinkError::ZeroModulus));
+    }
 }
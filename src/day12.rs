@@ -1,125 +1,270 @@
+use rayon::prelude::*;
+
 const SHIFTS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const DIAGONALS: [(i32, i32); 4] = [(-1, -1), (1, -1), (-1, 1), (1, 1)];
+const UNLABELED: usize = usize::MAX;
+const MIN_BAND_ROWS: usize = 32;
 
-fn neighbor_fence(
-    field: &[Vec<u8>],
-    visited: &mut [Vec<u8>],
-    fences: &mut [Vec<u8>],
-    x: usize,
-    y: usize,
-    i: usize,
-) -> i64 {
-    let shifts = if i >= 2 {
-        [SHIFTS[0], SHIFTS[1]]
-    } else {
-        [SHIFTS[2], SHIFTS[3]]
-    };
-    let width = field[0].len() as i32;
-    let height = field.len() as i32;
-    shifts
-        .iter()
-        .filter_map(|&shift| {
-            let (new_x, new_y) = (x as i32 + shift.0, y as i32 + shift.1);
-            if new_x >= 0 && new_x < width && new_y >= 0 && new_y < height {
-                Some((new_x as usize, new_y as usize))
-            } else {
-                None
+pub struct Region {
+    pub plant: u8,
+    pub cells: Vec<(usize, usize)>,
+    pub perimeter: usize,
+    pub sides: usize,
+    pub min: (usize, usize),
+    pub max: (usize, usize),
+    pub holes: usize,
+}
+
+impl Region {
+    pub fn area(&self) -> usize {
+        self.cells.len()
+    }
+
+    pub fn price(&self) -> usize {
+        self.area() * self.perimeter
+    }
+
+    pub fn bulk_price(&self) -> usize {
+        self.area() * self.sides
+    }
+}
+
+fn label_at(labels: &[Vec<usize>], x: i32, y: i32) -> Option<usize> {
+    if x < 0 || y < 0 {
+        return None;
+    }
+    labels.get(y as usize)?.get(x as usize).copied()
+}
+
+fn flood_band(field: &[Vec<u8>], labels: &mut [Vec<usize>], first_row: usize, width: usize) {
+    let mut stack = vec![];
+    for y in 0..field.len() {
+        for x in 0..field[y].len() {
+            if labels[y][x] != UNLABELED {
+                continue;
             }
-        })
-        .filter(|&(new_x, new_y)| visited[new_y][new_x] == field[y][x])
-        .filter(|&(new_x, new_y)| {
-            field[y][x] == field[new_y][new_x] && (fences[new_y][new_x] & (1 << i) != 0)
-        })
-        .count() as i64
+            let label = (first_row + y) * width + x;
+            labels[y][x] = label;
+            stack.push((x, y));
+            while let Some((x, y)) = stack.pop() {
+                for shift in SHIFTS {
+                    let (new_x, new_y) = (x as i32 + shift.0, y as i32 + shift.1);
+                    if label_at(labels, new_x, new_y) == Some(UNLABELED) {
+                        let (new_x, new_y) = (new_x as usize, new_y as usize);
+                        if field[new_y][new_x] == field[y][x] {
+                            labels[new_y][new_x] = label;
+                            stack.push((new_x, new_y));
+                        }
+                    }
+                }
+            }
+        }
+    }
 }
 
-fn dfs(
-    field: &[Vec<u8>],
-    visited: &mut [Vec<u8>],
-    fences: &mut [Vec<u8>],
-    x: usize,
-    y: usize,
-    value: u8,
-    reuse_fence: bool,
-) -> (i64, i64) {
-    if visited[y][x] != 0 {
-        return (0, 0);
-    }
-
-    let width = field[0].len() as i32;
-    let height = field.len() as i32;
-
-    visited[y][x] = value;
-    let (p, a) = SHIFTS
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        UnionFind {
+            parent: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parent[a.max(b)] = a.min(b);
+    }
+}
+
+fn label_bands(field: &[Vec<u8>], band: usize) -> (Vec<Vec<usize>>, usize) {
+    let width = field.iter().map(|line| line.len()).max().unwrap_or(0);
+    let mut labels = field
         .iter()
-        .map(|shift| (x as i32 + shift.0, y as i32 + shift.1))
+        .map(|line| vec![UNLABELED; line.len()])
+        .collect::<Vec<_>>();
+
+    // Flood every band of rows independently, labelling each piece with the
+    // index of its first cell, then stitch the pieces across band borders.
+    labels
+        .par_chunks_mut(band)
+        .zip(field.par_chunks(band))
         .enumerate()
-        .fold((0, 0), |acc, (i, new_pos)| {
-            if new_pos.0 < 0 || new_pos.0 >= width || new_pos.1 < 0 || new_pos.1 >= height {
-                if reuse_fence {
-                    fences[y][x] |= 1 << i;
-                    let fence = neighbor_fence(field, visited, fences, x, y, i);
-                    (acc.0 + 1 - fence, acc.1)
-                } else {
-                    (acc.0 + 1, acc.1)
+        .for_each(|(i, (labels, rows))| flood_band(rows, labels, i * band, width));
+
+    let mut sets = UnionFind::new(width * field.len());
+    for y in (band..field.len()).step_by(band) {
+        for x in 0..field[y - 1].len().min(field[y].len()) {
+            if field[y - 1][x] == field[y][x] {
+                sets.union(labels[y - 1][x], labels[y][x]);
+            }
+        }
+    }
+
+    let mut dense = vec![UNLABELED; width * field.len()];
+    let mut count = 0;
+    for line in labels.iter_mut() {
+        for label in line.iter_mut() {
+            let root = sets.find(*label);
+            if dense[root] == UNLABELED {
+                dense[root] = count;
+                count += 1;
+            }
+            *label = dense[root];
+        }
+    }
+    (labels, count)
+}
+
+pub fn label_regions(field: &[Vec<u8>]) -> (Vec<Vec<usize>>, usize) {
+    let band = field
+        .len()
+        .div_ceil(rayon::current_num_threads())
+        .max(MIN_BAND_ROWS);
+    label_bands(field, band)
+}
+
+fn count_holes(cells: &[(usize, usize)], min: (usize, usize), max: (usize, usize)) -> usize {
+    // Work in the bounding box grown by one cell, so the outside is a single
+    // connected ring. Fences meeting at a corner can't be walked through, so
+    // the complement is 4-connected as well.
+    let width = max.0 - min.0 + 3;
+    let height = max.1 - min.1 + 3;
+    let mut blocked = vec![vec![false; width]; height];
+    for &(x, y) in cells {
+        blocked[y - min.1 + 1][x - min.0 + 1] = true;
+    }
+
+    let fill = |blocked: &mut Vec<Vec<bool>>, x: usize, y: usize| {
+        let mut stack = vec![(x, y)];
+        blocked[y][x] = true;
+        while let Some((x, y)) = stack.pop() {
+            for shift in SHIFTS {
+                let (new_x, new_y) = (x as i32 + shift.0, y as i32 + shift.1);
+                if new_x < 0 || new_y < 0 || new_x >= width as i32 || new_y >= height as i32 {
+                    continue;
                 }
-            } else {
-                let new_x = new_pos.0 as usize;
-                let new_y = new_pos.1 as usize;
-                if field[new_y][new_x] != value {
-                    if reuse_fence {
-                        fences[y][x] |= 1 << i;
-                        let fence = neighbor_fence(field, visited, fences, x, y, i);
-                        (acc.0 + 1 - fence, acc.1)
-                    } else {
-                        (acc.0 + 1, acc.1)
-                    }
-                } else {
-                    let (p, a) = dfs(field, visited, fences, new_x, new_y, value, reuse_fence);
-                    (acc.0 + p, acc.1 + a)
+                let (new_x, new_y) = (new_x as usize, new_y as usize);
+                if !blocked[new_y][new_x] {
+                    blocked[new_y][new_x] = true;
+                    stack.push((new_x, new_y));
                 }
             }
-        });
-    (p, a + 1)
+        }
+    };
+
+    fill(&mut blocked, 0, 0);
+    let mut holes = 0;
+    for y in 0..height {
+        for x in 0..width {
+            if !blocked[y][x] {
+                fill(&mut blocked, x, y);
+                holes += 1;
+            }
+        }
+    }
+    holes
 }
 
-pub fn part1(input: &str) -> i64 {
-    let field = input
+fn build_region(
+    field: &[Vec<u8>],
+    labels: &[Vec<usize>],
+    label: usize,
+    cells: Vec<(usize, usize)>,
+) -> Region {
+    let inside = |x: i32, y: i32| label_at(labels, x, y) == Some(label);
+
+    let perimeter = cells
+        .iter()
+        .map(|&(x, y)| {
+            SHIFTS
+                .iter()
+                .filter(|shift| !inside(x as i32 + shift.0, y as i32 + shift.1))
+                .count()
+        })
+        .sum();
+
+    // Every side of a polygon starts and ends in a corner, so counting
+    // corners (convex and concave) gives the number of sides.
+    let sides = cells
+        .iter()
+        .map(|&(x, y)| {
+            let (x, y) = (x as i32, y as i32);
+            DIAGONALS
+                .iter()
+                .filter(|&&(dx, dy)| {
+                    let horizontal = inside(x + dx, y);
+                    let vertical = inside(x, y + dy);
+                    (!horizontal && !vertical)
+                        || (horizontal && vertical && !inside(x + dx, y + dy))
+                })
+                .count()
+        })
+        .sum();
+
+    let min = cells.iter().fold((usize::MAX, usize::MAX), |acc, &(x, y)| {
+        (acc.0.min(x), acc.1.min(y))
+    });
+    let max = cells
+        .iter()
+        .fold((0, 0), |acc, &(x, y)| (acc.0.max(x), acc.1.max(y)));
+    let holes = count_holes(&cells, min, max);
+
+    Region {
+        plant: field[cells[0].1][cells[0].0],
+        cells,
+        perimeter,
+        sides,
+        min,
+        max,
+        holes,
+    }
+}
+
+pub fn regions(field: &[Vec<u8>]) -> Vec<Region> {
+    let (labels, count) = label_regions(field);
+    let mut cells = vec![vec![]; count];
+    for (y, line) in labels.iter().enumerate() {
+        for (x, &label) in line.iter().enumerate() {
+            cells[label].push((x, y));
+        }
+    }
+    cells
+        .into_iter()
+        .enumerate()
+        .map(|(label, cells)| build_region(field, &labels, label, cells))
+        .collect()
+}
+
+pub fn parse(input: &str) -> Vec<Vec<u8>> {
+    input
         .trim()
         .lines()
         .map(|x| x.trim().as_bytes().to_vec())
-        .collect::<Vec<_>>();
-    let width = field[0].len();
-    let height = field.len();
-    let mut visited = vec![vec![0; width]; height];
-    let mut fences = vec![];
-    (0..height)
-        .map(|y| {
-            (0..width).fold(0, |acc, x| {
-                let (p, a) = dfs(&field, &mut visited, &mut fences, x, y, field[y][x], false);
-                acc + p * a
-            })
-        })
+        .collect()
+}
+
+pub fn part1(input: &str) -> i64 {
+    regions(&parse(input))
+        .iter()
+        .map(|region| region.price() as i64)
         .sum()
 }
 
 pub fn part2(input: &str) -> i64 {
-    let field = input
-        .trim()
-        .lines()
-        .map(|x| x.trim().as_bytes().to_vec())
-        .collect::<Vec<_>>();
-    let width = field[0].len();
-    let height = field.len();
-    let mut visited = vec![vec![0; width]; height];
-    let mut fences = vec![vec![0; width]; height];
-    (0..height)
-        .map(|y| {
-            (0..width).fold(0, |acc, x| {
-                let (p, a) = dfs(&field, &mut visited, &mut fences, x, y, field[y][x], true);
-                // println!("{} {} {}({},{})", p, a, field[y][x] as char, x, y);
-                acc + p * a
-            })
-        })
+    regions(&parse(input))
+        .iter()
+        .map(|region| region.bulk_price() as i64)
         .sum()
 }
 
@@ -182,4 +327,50 @@ mod tests {
         assert_eq!(part2(INPUT5), 368);
         assert_eq!(part2(INPUT3), 1206);
     }
+
+    #[test]
+    fn test_day12_regions() {
+        let regions = regions(&parse(INPUT1));
+        assert_eq!(regions.len(), 5);
+        let c = regions.iter().find(|region| region.plant == b'C').unwrap();
+        assert_eq!(c.area(), 4);
+        assert_eq!(c.perimeter, 10);
+        assert_eq!(c.sides, 8);
+        assert_eq!((c.min, c.max), ((2, 1), (3, 3)));
+        assert_eq!(c.holes, 0);
+
+        let regions = super::regions(&parse(INPUT2));
+        assert_eq!(regions.len(), 5);
+        let o = regions.iter().find(|region| region.plant == b'O').unwrap();
+        assert_eq!((o.area(), o.perimeter, o.sides, o.holes), (21, 36, 20, 4));
+        for x in regions.iter().filter(|region| region.plant == b'X') {
+            assert_eq!((x.area(), x.perimeter, x.sides, x.holes), (1, 4, 4, 0));
+        }
+
+        let regions = super::regions(&parse(INPUT5));
+        let a = regions.iter().find(|region| region.plant == b'A').unwrap();
+        assert_eq!((a.area(), a.sides, a.holes), (28, 12, 2));
+        assert_eq!((a.min, a.max), ((0, 0), (5, 5)));
+    }
+
+    #[test]
+    fn test_day12_bands() {
+        for input in [INPUT1, INPUT2, INPUT3, INPUT4, INPUT5] {
+            let field = parse(input);
+            let expected = label_bands(&field, field.len());
+            for band in 1..field.len() {
+                assert_eq!(label_bands(&field, band), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_day12_huge_region() {
+        let field = vec![vec![b'A'; 1000]; 1000];
+        let regions = regions(&field);
+        assert_eq!(regions.len(), 1);
+        assert_eq!(regions[0].area(), 1_000_000);
+        assert_eq!(regions[0].perimeter, 4000);
+        assert_eq!(regions[0].sides, 4);
+    }
 }
@@ -1,36 +1,166 @@
 use regex::Regex;
 
-struct Machine {
-    ax: i64,
-    ay: i64,
-    bx: i64,
-    by: i64,
-    px: i64,
-    py: i64,
+pub struct Machine {
+    pub ax: i64,
+    pub ay: i64,
+    pub bx: i64,
+    pub by: i64,
+    pub px: i64,
+    pub py: i64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Rules {
+    pub a_cost: i64,
+    pub b_cost: i64,
+    pub prize_offset: i64,
+    pub max_presses: Option<i64>,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            a_cost: 3,
+            b_cost: 1,
+            prize_offset: 0,
+            max_presses: None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Solution {
+    pub a: i64,
+    pub b: i64,
+    pub tokens: i64,
+}
+
+fn extended_gcd(a: i128, b: i128) -> (i128, i128, i128) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (g, x, y) = extended_gcd(b, a % b);
+        (g, y, x - (a / b) * y)
+    }
+}
+
+fn div_floor(a: i128, b: i128) -> i128 {
+    let q = a / b;
+    if a % b != 0 && (a < 0) != (b < 0) {
+        q - 1
+    } else {
+        q
+    }
+}
+
+fn div_ceil(a: i128, b: i128) -> i128 {
+    -div_floor(-a, b)
+}
+
+// Cheapest non-negative (a, b) with a * u + b * v = w and both presses
+// within the limit.
+fn solve_line(u: i128, v: i128, w: i128, rules: &Rules) -> Option<(i128, i128)> {
+    let limit = rules.max_presses.map(|x| x as i128);
+    if u == 0 && v == 0 {
+        return if w == 0 { Some((0, 0)) } else { None };
+    }
+
+    let (g, x, y) = extended_gcd(u, v);
+    if w % g != 0 {
+        return None;
+    }
+    let (a0, b0) = (x * (w / g), y * (w / g));
+    let (sa, sb) = (v / g, u / g);
+
+    // a = a0 + t * sa and b = b0 - t * sb, each bounded by 0 and the limit.
+    let mut low: Option<i128> = None;
+    let mut high: Option<i128> = None;
+    let mut bound = |value: i128, step: i128, min: i128, max: Option<i128>| {
+        if step == 0 {
+            return value >= min && max.is_none_or(|max| value <= max);
+        }
+        // Dividing by a negative step flips which side each bound lands on.
+        let (lower, upper) = if step > 0 {
+            (
+                Some(div_ceil(min - value, step)),
+                max.map(|max| div_floor(max - value, step)),
+            )
+        } else {
+            (
+                max.map(|max| div_ceil(max - value, step)),
+                Some(div_floor(min - value, step)),
+            )
+        };
+        if let Some(lower) = lower {
+            low = Some(low.map_or(lower, |low| low.max(lower)));
+        }
+        if let Some(upper) = upper {
+            high = Some(high.map_or(upper, |high| high.min(upper)));
+        }
+        true
+    };
+    if !bound(a0, sa, 0, limit) || !bound(b0, -sb, 0, limit) {
+        return None;
+    }
+    if let (Some(low), Some(high)) = (low, high) {
+        if low > high {
+            return None;
+        }
+    }
+
+    let slope = rules.a_cost as i128 * sa - rules.b_cost as i128 * sb;
+    let t = match slope.cmp(&0) {
+        std::cmp::Ordering::Greater => low?,
+        std::cmp::Ordering::Less => high?,
+        std::cmp::Ordering::Equal => low.or(high).unwrap_or(0),
+    };
+    Some((a0 + t * sa, b0 - t * sb))
 }
 
 impl Machine {
-    fn price(&self) -> Option<i64> {
-        let d = self.ax * self.by - self.ay * self.bx;
-        if d == 0 {
-            if self.px % self.ax == 0 && self.ay * (self.px / self.ax) == self.py {
-                Some((3 * self.px / self.ax).min(self.py / self.ay))
+    pub fn solve(&self, rules: &Rules) -> Option<Solution> {
+        let (ax, ay, bx, by) = (
+            self.ax as i128,
+            self.ay as i128,
+            self.bx as i128,
+            self.by as i128,
+        );
+        let px = self.px as i128 + rules.prize_offset as i128;
+        let py = self.py as i128 + rules.prize_offset as i128;
+
+        let d = ax * by - ay * bx;
+        let (a, b) = if d == 0 {
+            // Collinear buttons: all solutions of one axis agree on the other
+            // axis, so solve one axis and verify the other.
+            if ax != 0 || bx != 0 {
+                solve_line(ax, bx, px, rules)?
             } else {
-                None
+                solve_line(ay, by, py, rules)?
             }
         } else {
-            let a = (self.px * self.by - self.py * self.bx) / d;
-            let b = (self.ax * self.py - self.ay * self.px) / d;
-            if self.ax * a + self.bx * b == self.px && self.ay * a + self.by * b == self.py {
-                Some(3 * a + b)
-            } else {
-                None
+            let a = (px * by - py * bx) / d;
+            let b = (ax * py - ay * px) / d;
+            let limit = rules.max_presses.map_or(i128::MAX, |x| x as i128);
+            if a < 0 || b < 0 || a > limit || b > limit {
+                return None;
             }
+            (a, b)
+        };
+        if ax * a + bx * b != px || ay * a + by * b != py {
+            return None;
         }
+        assert!(a >= 0 && b >= 0, "negative presses {} and {}", a, b);
+
+        let tokens = rules.a_cost as i128 * a + rules.b_cost as i128 * b;
+        Some(Solution {
+            a: a.try_into().ok()?,
+            b: b.try_into().ok()?,
+            tokens: tokens.try_into().ok()?,
+        })
     }
 }
 
-fn parse(input: &str) -> Vec<Machine> {
+pub fn parse(input: &str) -> Vec<Machine> {
     let re = Regex::new(
         r"Button A: X\+(\d+), Y\+(\d+)\s+Button B: X\+(\d+), Y\+(\d+)\s+Prize: X=(\d+), Y=(\d+)",
     )
@@ -56,20 +186,23 @@ fn parse(input: &str) -> Vec<Machine> {
 }
 
 pub fn part1(input: &str) -> i64 {
+    let rules = Rules::default();
     parse(input)
         .iter()
-        .filter_map(|machine| machine.price())
+        .filter_map(|machine| machine.solve(&rules))
+        .map(|solution| solution.tokens)
         .sum()
 }
 
 pub fn part2(input: &str) -> i64 {
+    let rules = Rules {
+        prize_offset: 10_000_000_000_000,
+        ..Rules::default()
+    };
     parse(input)
-        .iter_mut()
-        .filter_map(|machine| {
-            machine.px += 10_000_000_000_000;
-            machine.py += 10_000_000_000_000;
-            machine.price()
-        })
+        .iter()
+        .filter_map(|machine| machine.solve(&rules))
+        .map(|solution| solution.tokens)
         .sum()
 }
 
@@ -103,4 +236,119 @@ mod tests {
     fn test_day13_part2() {
         assert_eq!(part2(INPUT), 875318608908);
     }
+
+    fn machine(a: (i64, i64), b: (i64, i64), prize: (i64, i64)) -> Machine {
+        Machine {
+            ax: a.0,
+            ay: a.1,
+            bx: b.0,
+            by: b.1,
+            px: prize.0,
+            py: prize.1,
+        }
+    }
+
+    #[test]
+    fn test_day13_presses() {
+        let machines = parse(INPUT);
+        let rules = Rules {
+            max_presses: Some(100),
+            ..Rules::default()
+        };
+        assert_eq!(
+            machines[0].solve(&rules),
+            Some(Solution {
+                a: 80,
+                b: 40,
+                tokens: 280
+            })
+        );
+        assert_eq!(machines[1].solve(&rules), None);
+        let rules = Rules {
+            max_presses: Some(50),
+            ..Rules::default()
+        };
+        assert_eq!(machines[0].solve(&rules), None);
+    }
+
+    #[test]
+    fn test_day13_collinear() {
+        let rules = Rules::default();
+        let m = machine((1, 1), (3, 3), (10, 10));
+        assert_eq!(
+            m.solve(&rules),
+            Some(Solution {
+                a: 1,
+                b: 3,
+                tokens: 6
+            })
+        );
+
+        let rules = Rules {
+            a_cost: 1,
+            b_cost: 5,
+            ..Rules::default()
+        };
+        assert_eq!(m.solve(&rules).map(|s| (s.a, s.b)), Some((10, 0)));
+        let limited = Rules {
+            max_presses: Some(5),
+            ..rules
+        };
+        assert_eq!(
+            m.solve(&limited),
+            Some(Solution {
+                a: 4,
+                b: 2,
+                tokens: 14
+            })
+        );
+
+        assert_eq!(machine((1, 1), (3, 3), (10, 11)).solve(&rules), None);
+        assert_eq!(machine((2, 2), (4, 4), (7, 7)).solve(&rules), None);
+        assert_eq!(
+            machine((0, 0), (2, 3), (4, 6))
+                .solve(&rules)
+                .map(|s| (s.a, s.b)),
+            Some((0, 2))
+        );
+        assert_eq!(
+            machine((0, 0), (0, 0), (0, 0))
+                .solve(&rules)
+                .map(|s| s.tokens),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn test_day13_collinear_remainder() {
+        let rules = Rules::default();
+        assert_eq!(
+            machine((7, 7), (2, 2), (8, 8)).solve(&rules),
+            Some(Solution {
+                a: 0,
+                b: 4,
+                tokens: 4
+            })
+        );
+        assert_eq!(
+            machine((7, 7), (2, 2), (23, 23)).solve(&rules),
+            Some(Solution {
+                a: 3,
+                b: 1,
+                tokens: 10
+            })
+        );
+
+        for (u, v) in [(7, 2), (2, 7), (13, 3), (4, 6)] {
+            for prize in 0..80 {
+                let expected = (0..=prize / u)
+                    .filter(|a| (prize - a * u) % v == 0)
+                    .map(|a| 3 * a + (prize - a * u) / v)
+                    .min();
+                let solution = machine((u, u), (v, v), (prize, prize)).solve(&rules);
+                assert_eq!(solution.map(|s| s.tokens), expected);
+                assert!(solution.is_none_or(|s| s.a >= 0 && s.b >= 0));
+            }
+        }
+    }
 }
@@ -1,5 +1,10 @@
+use rayon::prelude::*;
 use regex::Regex;
 use std::cmp::Ordering;
+use std::fs;
+use std::io;
+use std::ops::Range;
+use std::path::Path;
 
 struct Robot {
     x: i32,
@@ -47,67 +52,384 @@ fn parse(input: &str) -> Vec<Robot> {
         .collect()
 }
 
-fn simulate(robots: &[Robot], width: i32, height: i32, steps: i32) -> i32 {
-    robots
-        .iter()
-        .fold([0; 4], |mut acc, robot| {
-            let pos = robot.apply_steps(steps, width, height);
-            if let Some(index) = Robot::quadrant(pos.0, pos.1, width, height) {
-                acc[index] += 1;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageFormat {
+    Pbm,
+    Pgm,
+}
+
+pub struct RobotRoom {
+    robots: Vec<Robot>,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl RobotRoom {
+    pub fn new(input: &str, width: i32, height: i32) -> Self {
+        RobotRoom {
+            robots: parse(input),
+            width,
+            height,
+        }
+    }
+
+    pub fn period(&self) -> i32 {
+        (self.width as i64 * self.height as i64 / gcd(self.width as i64, self.height as i64)) as i32
+    }
+
+    pub fn positions(&self, steps: i32) -> Vec<(i32, i32)> {
+        self.robots
+            .iter()
+            .map(|robot| robot.apply_steps(steps, self.width, self.height))
+            .collect()
+    }
+
+    pub fn counts(&self, steps: i32) -> Vec<Vec<u32>> {
+        let mut counts = vec![vec![0; self.width as usize]; self.height as usize];
+        for (x, y) in self.positions(steps) {
+            counts[y as usize][x as usize] += 1;
+        }
+        counts
+    }
+
+    pub fn safety_factor(&self, steps: i32) -> i32 {
+        self.positions(steps)
+            .iter()
+            .fold([0; 4], |mut acc, &(x, y)| {
+                if let Some(index) = Robot::quadrant(x, y, self.width, self.height) {
+                    acc[index] += 1;
+                }
+                acc
+            })
+            .iter()
+            .product()
+    }
+
+    pub fn render(&self, steps: i32) -> String {
+        self.counts(steps)
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|&count| match count {
+                        0 => '.',
+                        1..=9 => char::from_digit(count, 10).unwrap(),
+                        _ => '#',
+                    })
+                    .collect::<String>()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn render_frames(&self, steps: Range<i32>) -> Vec<String> {
+        steps.into_par_iter().map(|i| self.render(i)).collect()
+    }
+
+    pub fn image(&self, steps: i32, format: ImageFormat) -> String {
+        let counts = self.counts(steps);
+        let (header, max) = match format {
+            ImageFormat::Pbm => (format!("P1\n{} {}\n", self.width, self.height), 1),
+            ImageFormat::Pgm => {
+                let max = counts.iter().flatten().copied().max().unwrap_or(0).max(1);
+                (
+                    format!("P2\n{} {}\n{}\n", self.width, self.height, max),
+                    max,
+                )
             }
-            acc
+        };
+        let pixels = counts
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|&count| match format {
+                        ImageFormat::Pbm => count.min(max).to_string(),
+                        ImageFormat::Pgm => (max - count).to_string(),
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        header + &pixels + "\n"
+    }
+
+    pub fn export(&self, dir: &Path, steps: Range<i32>, format: ImageFormat) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let extension = match format {
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Pgm => "pgm",
+        };
+        steps.into_par_iter().try_for_each(|i| {
+            fs::write(
+                dir.join(format!("frame{:05}.{}", i, extension)),
+                self.image(i, format),
+            )
         })
-        .iter()
-        .product()
+    }
 }
 
 pub fn part1(input: &str) -> i32 {
-    let robots = parse(input);
-    simulate(&robots, 101, 103, 100)
+    RobotRoom::new(input, 101, 103).safety_factor(100)
 }
 
-pub fn part2(input: &str) -> i32 {
-    let width = 101;
-    let height = 103;
-    let robots = parse(input);
-    let (mut x, mut y) = ((1_000_000.0, 0), (1_000_000.0, 0));
-    for i in 0..width.max(height) {
-        let sums = robots.iter().fold((0, 0), |acc, robot| {
-            let (x, y) = robot.apply_steps(i, width, height);
-            (acc.0 + x, acc.1 + y)
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a.abs()
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+fn extended_gcd(a: i64, b: i64) -> (i64, i64, i64) {
+    if b == 0 {
+        (a, 1, 0)
+    } else {
+        let (g, x, y) = extended_gcd(b, a % b);
+        (g, y, x - (a / b) * y)
+    }
+}
+
+fn mod_inverse(a: i64, m: i64) -> Option<i64> {
+    let (g, x, _) = extended_gcd(a.rem_euclid(m), m);
+    if g == 1 {
+        Some(x.rem_euclid(m))
+    } else {
+        None
+    }
+}
+
+// Smallest t >= 0 with t = a (mod m) and t = b (mod n), if one exists.
+pub fn crt(a: i64, m: i64, b: i64, n: i64) -> Option<i64> {
+    let g = gcd(m, n);
+    if (b - a) % g != 0 {
+        return None;
+    }
+    let (m_g, n_g) = (m / g, n / g);
+    let lcm = m_g * n;
+    let k = ((b - a) / g).rem_euclid(n_g) * mod_inverse(m_g, n_g)? % n_g;
+    Some((a + m * k).rem_euclid(lcm))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Candidate {
+    pub step: i32,
+    pub score: f64,
+}
+
+fn rank(mut candidates: Vec<Candidate>, limit: usize) -> Vec<Candidate> {
+    candidates.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.step.cmp(&b.step)));
+    candidates.truncate(limit);
+    candidates
+}
+
+pub trait Detector: Sync {
+    fn name(&self) -> &'static str;
+
+    fn score(&self, room: &RobotRoom, positions: &[(i32, i32)]) -> f64;
+
+    fn candidates(&self, room: &RobotRoom, limit: usize) -> Vec<Candidate> {
+        let candidates = (0..room.period())
+            .into_par_iter()
+            .map(|step| Candidate {
+                step,
+                score: self.score(room, &room.positions(step)),
+            })
+            .collect();
+        rank(candidates, limit)
+    }
+}
+
+pub struct AxisVariance {
+    pub phases_per_axis: usize,
+}
+
+impl AxisVariance {
+    fn best_phases(values: impl Fn(i32) -> Vec<i32>, size: i32, count: usize) -> Vec<(i32, f64)> {
+        let mut phases = (0..size)
+            .map(|i| {
+                let values = values(i);
+                let n = values.len().max(1) as f64;
+                let mean = values.iter().map(|&v| v as f64).sum::<f64>() / n;
+                let variance = values
+                    .iter()
+                    .map(|&v| (v as f64 - mean) * (v as f64 - mean))
+                    .sum::<f64>()
+                    / n;
+                (i, variance)
+            })
+            .collect::<Vec<_>>();
+        phases.sort_by(|a, b| a.1.total_cmp(&b.1));
+        phases.truncate(count);
+        phases
+    }
+}
+
+impl Detector for AxisVariance {
+    fn name(&self) -> &'static str {
+        "axis variance"
+    }
+
+    fn score(&self, room: &RobotRoom, positions: &[(i32, i32)]) -> f64 {
+        let n = positions.len().max(1) as f64;
+        let (mean_x, mean_y) = positions.iter().fold((0.0, 0.0), |acc, &(x, y)| {
+            (acc.0 + x as f64 / n, acc.1 + y as f64 / n)
         });
-        let (mean_x, mean_y) = (
-            sums.0 as f64 / robots.len() as f64,
-            sums.1 as f64 / robots.len() as f64,
-        );
-        let vars = robots.iter().fold((0.0, 0.0), |acc, robot| {
-            let (x, y) = robot.apply_steps(i, width, height);
+        let (var_x, var_y) = positions.iter().fold((0.0, 0.0), |acc, &(x, y)| {
             (
-                acc.0 + (mean_x - x as f64) * (mean_x - x as f64),
-                acc.1 + (mean_y - y as f64) * (mean_y - y as f64),
+                acc.0 + (x as f64 - mean_x) * (x as f64 - mean_x) / n,
+                acc.1 + (y as f64 - mean_y) * (y as f64 - mean_y) / n,
             )
         });
-        if vars.0 < x.0 {
-            x.0 = vars.0;
-            x.1 = i;
+        -(var_x / (room.width as f64 * room.width as f64)
+            + var_y / (room.height as f64 * room.height as f64))
+    }
+
+    // The x coordinates repeat every `width` steps and the y coordinates every
+    // `height` steps, so each axis is minimized on its own and the phases are
+    // joined with the Chinese remainder theorem.
+    fn candidates(&self, room: &RobotRoom, limit: usize) -> Vec<Candidate> {
+        let xs = Self::best_phases(
+            |i| room.positions(i).iter().map(|p| p.0).collect(),
+            room.width,
+            self.phases_per_axis,
+        );
+        let ys = Self::best_phases(
+            |i| room.positions(i).iter().map(|p| p.1).collect(),
+            room.height,
+            self.phases_per_axis,
+        );
+        let (w2, h2) = (
+            room.width as f64 * room.width as f64,
+            room.height as f64 * room.height as f64,
+        );
+        let candidates = xs
+            .iter()
+            .flat_map(|&(x, var_x)| {
+                ys.iter().filter_map(move |&(y, var_y)| {
+                    crt(x as i64, room.width as i64, y as i64, room.height as i64).map(|step| {
+                        Candidate {
+                            step: step as i32,
+                            score: -(var_x / w2 + var_y / h2),
+                        }
+                    })
+                })
+            })
+            .collect();
+        rank(candidates, limit)
+    }
+}
+
+pub struct NoOverlap;
+
+impl Detector for NoOverlap {
+    fn name(&self) -> &'static str {
+        "no overlap"
+    }
+
+    fn score(&self, room: &RobotRoom, positions: &[(i32, i32)]) -> f64 {
+        let mut occupied = vec![false; (room.width * room.height) as usize];
+        let distinct = positions
+            .iter()
+            .filter(|&&(x, y)| {
+                let cell = &mut occupied[(y * room.width + x) as usize];
+                !std::mem::replace(cell, true)
+            })
+            .count();
+        distinct as f64 / positions.len().max(1) as f64
+    }
+}
+
+pub struct LargestComponent;
+
+impl Detector for LargestComponent {
+    fn name(&self) -> &'static str {
+        "largest component"
+    }
+
+    fn score(&self, room: &RobotRoom, positions: &[(i32, i32)]) -> f64 {
+        let (width, height) = (room.width, room.height);
+        let mut occupied = vec![false; (width * height) as usize];
+        for &(x, y) in positions {
+            occupied[(y * width + x) as usize] = true;
         }
-        if vars.1 < y.0 {
-            y.0 = vars.1;
-            y.1 = i;
+        let mut largest = 0;
+        let mut stack = vec![];
+        for start in 0..occupied.len() {
+            if !occupied[start] {
+                continue;
+            }
+            occupied[start] = false;
+            stack.push(start as i32);
+            let mut size = 0;
+            while let Some(cell) = stack.pop() {
+                size += 1;
+                let (x, y) = (cell % width, cell / width);
+                for (dx, dy) in [(-1, 0), (1, 0), (0, -1), (0, 1)] {
+                    let (nx, ny) = (x + dx, y + dy);
+                    if nx >= 0 && nx < width && ny >= 0 && ny < height {
+                        let next = ny * width + nx;
+                        if occupied[next as usize] {
+                            occupied[next as usize] = false;
+                            stack.push(next);
+                        }
+                    }
+                }
+            }
+            largest = largest.max(size);
+        }
+        largest as f64 / positions.len().max(1) as f64
+    }
+}
+
+pub struct CompressedSize;
+
+impl Detector for CompressedSize {
+    fn name(&self) -> &'static str {
+        "compressed size"
+    }
+
+    // Length of the run-length encoding of the occupancy bitmap, relative to
+    // what scattered robots would need. Structured pictures compress better.
+    fn score(&self, room: &RobotRoom, positions: &[(i32, i32)]) -> f64 {
+        let mut occupied = vec![false; (room.width * room.height) as usize];
+        for &(x, y) in positions {
+            occupied[(y * room.width + x) as usize] = true;
         }
+        let runs = 1 + occupied.windows(2).filter(|w| w[0] != w[1]).count();
+        1.0 - runs as f64 / (2 * positions.len() + 1) as f64
     }
+}
 
-    for i in 1..20000 {
-        if i % width == x.1 && i % height == y.1 {
-            return i;
+pub fn detect(room: &RobotRoom, detectors: &[&dyn Detector], limit: usize) -> Vec<Candidate> {
+    let mut scores: Vec<Candidate> = vec![];
+    for detector in detectors {
+        for (rank, candidate) in detector.candidates(room, limit).iter().enumerate() {
+            let points = (limit - rank) as f64;
+            match scores.iter_mut().find(|c| c.step == candidate.step) {
+                Some(c) => c.score += points,
+                None => scores.push(Candidate {
+                    step: candidate.step,
+                    score: points,
+                }),
+            }
         }
     }
-    0
+    rank(scores, limit)
+}
+
+pub fn part2(input: &str) -> i32 {
+    let room = RobotRoom::new(input, 101, 103);
+    AxisVariance { phases_per_axis: 1 }
+        .candidates(&room, 1)
+        .first()
+        .map_or(0, |candidate| candidate.step)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
     const INPUT: &str = "
         p=0,4 v=3,-3
@@ -125,10 +447,101 @@ mod tests {
 
     #[test]
     fn test_day14_part1() {
-        let mut robots = parse(INPUT);
-        assert_eq!(simulate(&mut robots, 11, 7, 100), 12);
+        let room = RobotRoom::new(INPUT, 11, 7);
+        assert_eq!(room.safety_factor(100), 12);
+    }
+
+    #[test]
+    fn test_day14_render() {
+        let room = RobotRoom::new(INPUT, 11, 7);
+        assert_eq!(
+            room.render(0),
+            "1.12.......\n\
+             ...........\n\
+             ...........\n\
+             ......11.11\n\
+             1.1........\n\
+             .........1.\n\
+             .......1..."
+        );
+        assert_eq!(
+            room.render(100),
+            "......2..1.\n\
+             ...........\n\
+             1..........\n\
+             .11........\n\
+             .....1.....\n\
+             ...12......\n\
+             .1....1...."
+        );
+        assert_eq!(room.render_frames(99..101)[1], room.render(100));
+    }
+
+    #[test]
+    fn test_day14_export() {
+        let room = RobotRoom::new(INPUT, 11, 7);
+        let pgm = room.image(100, ImageFormat::Pgm);
+        assert!(pgm.starts_with("P2\n11 7\n2\n2 2 2 2 2 2 0 2 2 1 2\n"));
+        let pbm = room.image(100, ImageFormat::Pbm);
+        assert!(pbm.starts_with("P1\n11 7\n0 0 0 0 0 0 1 0 0 1 0\n"));
+
+        let dir = std::env::temp_dir().join("adventofcode2024-day14-export");
+        room.export(&dir, 0..3, ImageFormat::Pbm).unwrap();
+        for i in 0..3 {
+            let content = fs::read_to_string(dir.join(format!("frame{:05}.pbm", i))).unwrap();
+            assert_eq!(content, room.image(i, ImageFormat::Pbm));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn picture_room(width: i32, height: i32, step: i32) -> RobotRoom {
+        let mut rng = StdRng::seed_from_u64(14);
+        let robots = (0..20)
+            .flat_map(|y| (0..25).map(move |x| (x + 10, y + 5)))
+            .map(|(x, y)| {
+                let (vx, vy) = (rng.gen_range(-50..50), rng.gen_range(-50..50));
+                Robot {
+                    x: (x - vx * step).rem_euclid(width),
+                    y: (y - vy * step).rem_euclid(height),
+                    vx,
+                    vy,
+                }
+            })
+            .collect();
+        RobotRoom {
+            robots,
+            width,
+            height,
+        }
     }
 
     #[test]
-    fn test_day14_part2() {}
+    fn test_day14_part2() {
+        let room = picture_room(101, 103, 4321);
+        let detectors: [&dyn Detector; 4] = [
+            &AxisVariance { phases_per_axis: 3 },
+            &NoOverlap,
+            &LargestComponent,
+            &CompressedSize,
+        ];
+        for detector in detectors {
+            let candidates = detector.candidates(&room, 3);
+            assert_eq!(candidates[0].step, 4321, "{}", detector.name());
+            assert!(candidates.windows(2).all(|w| w[0].score >= w[1].score));
+        }
+        assert_eq!(detect(&room, &detectors, 3)[0].step, 4321);
+    }
+
+    #[test]
+    fn test_day14_non_coprime() {
+        assert_eq!(crt(2, 4, 4, 6), Some(10));
+        assert_eq!(crt(1, 4, 2, 6), None);
+        assert_eq!(crt(3, 101, 5, 103), Some(10305));
+
+        let room = picture_room(40, 60, 77);
+        assert_eq!(room.period(), 120);
+        let candidates = AxisVariance { phases_per_axis: 2 }.candidates(&room, 5);
+        assert_eq!(candidates[0].step, 77);
+        assert_eq!(LargestComponent.candidates(&room, 1)[0].step, 77);
+    }
 }
@@ -1,199 +1,594 @@
-fn parse(input: &str) -> (Vec<Vec<u8>>, Vec<u8>, (usize, usize)) {
-    let mut iter = input.split("\n\n");
-    let field = iter
-        .next()
-        .unwrap()
-        .trim()
-        .lines()
-        .map(|line| line.trim().as_bytes().to_vec())
-        .collect::<Vec<_>>();
-    let instructions = iter.next().unwrap().trim().as_bytes().to_vec();
-    for (y, line) in field.iter().enumerate() {
-        if let Some(x) = line.iter().position(|&ch| ch == b'@') {
-            return (field, instructions, (x, y));
-        }
-    }
-    panic!("Incorrect input");
-}
-
-fn move_box(
-    field: &mut Vec<Vec<u8>>,
-    x: usize,
-    y: usize,
-    dir: (i32, i32),
-) -> Option<(usize, usize)> {
-    match field[y][x] {
-        b'#' => None,
-        b'.' => Some((x, y)),
-        b'O' => move_box(
-            field,
-            (x as i32 + dir.0) as usize,
-            (y as i32 + dir.1) as usize,
-            dir,
-        ),
-        _ => panic!("Unexpected"),
-    }
-}
-
-fn is_possible_wide_box(field: &mut Vec<Vec<u8>>, x: usize, y: usize, dir: (i32, i32)) -> bool {
-    match field[y][x] {
-        b'#' => false,
-        b'.' => true,
-        b'[' => {
-            is_possible_wide_box(
-                field,
-                (x as i32 + dir.0) as usize,
-                (y as i32 + dir.1) as usize,
-                dir,
-            ) && (dir.0 != 0
-                || is_possible_wide_box(
-                    field,
-                    (x as i32 + dir.0 + 1) as usize,
-                    (y as i32 + dir.1) as usize,
-                    dir,
-                ))
-        }
-        b']' => {
-            is_possible_wide_box(
-                field,
-                (x as i32 + dir.0) as usize,
-                (y as i32 + dir.1) as usize,
-                dir,
-            ) && (dir.0 != 0
-                || is_possible_wide_box(
-                    field,
-                    (x as i32 + dir.0 - 1) as usize,
-                    (y as i32 + dir.1) as usize,
-                    dir,
-                ))
-        }
-        _ => panic!("Unexpected {}", field[y][x]),
-    }
-}
-
-fn move_wide_box(field: &mut Vec<Vec<u8>>, x: usize, y: usize, dir: (i32, i32)) {
-    if dir.0 != 0 {
-        match field[y][x] {
-            b'.' => (),
-            b'#' => panic!("Unexpected # at {} {}", x, y),
-            _ => {
-                move_wide_box(field, (x as i32 + dir.0) as usize, y, dir);
-                field[y][(x as i32 + dir.0) as usize] = field[y][x];
-                field[y][x] = b'.';
+use ahash::AHashSet;
+use std::fmt;
+
+const EMPTY: usize = usize::MAX;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Shape {
+    Single,
+    Wide,
+    Letter(u8),
+}
+
+#[derive(Clone)]
+struct Crate {
+    shape: Shape,
+    cells: Vec<(usize, usize)>,
+}
+
+impl Crate {
+    fn corner(&self) -> (usize, usize) {
+        self.cells
+            .iter()
+            .fold((usize::MAX, usize::MAX), |acc, &(x, y)| {
+                (acc.0.min(x), acc.1.min(y))
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Options {
+    pub strict: bool,
+    pub multiple_robots: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WarehouseError {
+    UnknownTile {
+        x: usize,
+        y: usize,
+        tile: u8,
+    },
+    UnclosedBox {
+        x: usize,
+        y: usize,
+    },
+    MissingRobot,
+    MultipleRobots {
+        x: usize,
+        y: usize,
+    },
+    OpenEdge {
+        x: usize,
+        y: usize,
+        direction: u8,
+    },
+    UnknownInstruction {
+        line: usize,
+        column: usize,
+        instruction: u8,
+    },
+    InvalidState {
+        line: usize,
+    },
+}
+
+impl fmt::Display for WarehouseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            WarehouseError::UnknownTile { x, y, tile } => {
+                write!(f, "unknown tile {:?} at {},{}", tile as char, x, y)
             }
-        };
-    } else {
-        match field[y][x] {
-            b'.' => (),
-            b'#' => panic!("Unexpected # at {} {}", x, y),
-            b']' => move_wide_box(field, x - 1, y, dir),
-            b'[' => {
-                move_wide_box(field, x, (y as i32 + dir.1) as usize, dir);
-                move_wide_box(field, x + 1, (y as i32 + dir.1) as usize, dir);
-                field[(y as i32 + dir.1) as usize][x] = b'[';
-                field[(y as i32 + dir.1) as usize][x + 1] = b']';
-                field[y][x] = b'.';
-                field[y][x + 1] = b'.';
+            WarehouseError::UnclosedBox { x, y } => write!(f, "unclosed box at {},{}", x, y),
+            WarehouseError::MissingRobot => write!(f, "no robot on the map"),
+            WarehouseError::MultipleRobots { x, y } => {
+                write!(f, "unexpected second robot at {},{}", x, y)
+            }
+            WarehouseError::OpenEdge { x, y, direction } => write!(
+                f,
+                "moving {} from {},{} leaves the map without hitting a wall",
+                direction as char, x, y
+            ),
+            WarehouseError::UnknownInstruction {
+                line,
+                column,
+                instruction,
+            } => write!(
+                f,
+                "unknown instruction {:?} at line {}, column {}",
+                instruction as char, line, column
+            ),
+            WarehouseError::InvalidState { line } => {
+                write!(f, "invalid saved state at line {}", line)
             }
-            _ => panic!("Unexpected"),
         }
     }
 }
 
-pub fn part1(input: &str) -> i64 {
-    let (mut field, instructions, (mut x, mut y)) = parse(input);
-    for direction in instructions {
-        let Some(dir) = (match direction {
-            b'^' => Some((0, -1)),
-            b'>' => Some((1, 0)),
-            b'v' => Some((0, 1)),
-            b'<' => Some((-1, 0)),
-            _ => None,
-        }) else {
-            continue;
-        };
+impl std::error::Error for WarehouseError {}
 
-        let target = ((x as i32 + dir.0) as usize, (y as i32 + dir.1) as usize);
-        let Some(next_position) = move_box(&mut field, target.0, target.1, dir) else {
-            continue;
-        };
+pub struct Move {
+    pub robot: usize,
+    pub direction: u8,
+    pub moved: bool,
+    pub boxes: Vec<(usize, usize)>,
+}
 
-        field[y][x] = field[next_position.1][next_position.0];
-        field[next_position.1][next_position.0] = field[target.1][target.0];
-        field[target.1][target.0] = b'@';
-        x = target.0;
-        y = target.1;
-    }
-    let mut result = 0;
-    for i in 0..field.len() {
-        for j in 0..field[0].len() {
-            if field[i][j] == b'O' {
-                result += (100 * i + j) as i64;
+struct Step {
+    robot: usize,
+    next: usize,
+    dir: (i32, i32),
+    crates: Vec<usize>,
+}
+
+pub struct Warehouse {
+    walls: Vec<Vec<bool>>,
+    ids: Vec<Vec<usize>>,
+    crates: Vec<Crate>,
+    robots: Vec<(usize, usize)>,
+    instructions: Vec<u8>,
+    next: usize,
+    turn: usize,
+    history: Vec<Step>,
+}
+
+// What the map alone cannot tell: which robot is which, whose turn it is and
+// which cells belong to which letter crate. Each entry keeps the line it was
+// read from to report errors against.
+#[derive(Default)]
+struct State {
+    robots: Option<(usize, Cells)>,
+    turn: (usize, usize),
+    crates: Vec<(usize, u8, Cells)>,
+}
+
+type Cells = Vec<(usize, usize)>;
+
+impl State {
+    fn parse(section: &str, first_line: usize) -> Result<Self, WarehouseError> {
+        let mut state = State::default();
+        for (i, line) in section.lines().enumerate() {
+            let line_number = first_line + i;
+            let error = WarehouseError::InvalidState { line: line_number };
+            let mut words = line.split_whitespace();
+            let cells = |words: std::str::SplitWhitespace| {
+                words
+                    .map(|word| {
+                        let (x, y) = word.split_once(',')?;
+                        Some((x.parse().ok()?, y.parse().ok()?))
+                    })
+                    .collect::<Option<Vec<_>>>()
+            };
+            match words.next() {
+                None => (),
+                Some("robots") => state.robots = Some((line_number, cells(words).ok_or(error)?)),
+                Some("turn") => {
+                    let turn = words
+                        .next()
+                        .and_then(|turn| turn.parse().ok())
+                        .filter(|_| words.next().is_none())
+                        .ok_or(error)?;
+                    state.turn = (line_number, turn);
+                }
+                Some("crate") => {
+                    let letter = match words.next().map(str::as_bytes) {
+                        Some(&[letter]) if letter.is_ascii_lowercase() => letter,
+                        _ => return Err(error),
+                    };
+                    let cells = cells(words).filter(|cells| !cells.is_empty());
+                    state
+                        .crates
+                        .push((line_number, letter, cells.ok_or(error)?));
+                }
+                Some(_) => return Err(error),
             }
         }
+        Ok(state)
     }
-    result
 }
 
-pub fn part2(input: &str) -> i64 {
-    let (mut field, instructions, (mut x, mut y)) = parse(input);
-    let mut wide_field = vec![];
-    for line in field {
-        let mut new_line = vec![];
-        for ch in line {
-            match ch {
-                b'.' => {
-                    new_line.push(b'.');
-                    new_line.push(b'.');
+fn direction(instruction: u8) -> Option<(i32, i32)> {
+    match instruction {
+        b'^' => Some((0, -1)),
+        b'>' => Some((1, 0)),
+        b'v' => Some((0, 1)),
+        b'<' => Some((-1, 0)),
+        _ => None,
+    }
+}
+
+fn shift((x, y): (usize, usize), dir: (i32, i32)) -> Option<(usize, usize)> {
+    let (x, y) = (x as i32 + dir.0, y as i32 + dir.1);
+    if x < 0 || y < 0 {
+        None
+    } else {
+        Some((x as usize, y as usize))
+    }
+}
+
+impl Warehouse {
+    pub fn parse(input: &str) -> Result<Self, WarehouseError> {
+        Self::parse_with(input, Options::default())
+    }
+
+    pub fn parse_with(input: &str, options: Options) -> Result<Self, WarehouseError> {
+        let mut iter = input.split("\n\n");
+        let map = iter.next().unwrap_or("");
+        let field = map
+            .trim()
+            .lines()
+            .map(|line| line.trim().as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let moves = iter.next().unwrap_or("");
+        let state_line = input[..(map.len() + moves.len() + 4).min(input.len())]
+            .matches('\n')
+            .count()
+            + 1;
+        let state = State::parse(iter.next().unwrap_or(""), state_line)?;
+        let instructions = moves
+            .bytes()
+            .filter(|ch| !ch.is_ascii_whitespace())
+            .collect::<Vec<_>>();
+        if options.strict {
+            // Point at the character in the input itself, counting lines and
+            // columns from 1 like an editor does.
+            let offset = map.len() + 2;
+            if let Some(index) = moves
+                .bytes()
+                .position(|ch| !ch.is_ascii_whitespace() && direction(ch).is_none())
+            {
+                let before = &input.as_bytes()[..offset + index];
+                let line_start = before
+                    .iter()
+                    .rposition(|&ch| ch == b'\n')
+                    .map_or(0, |i| i + 1);
+                return Err(WarehouseError::UnknownInstruction {
+                    line: before.iter().filter(|&&ch| ch == b'\n').count() + 1,
+                    column: before.len() - line_start + 1,
+                    instruction: input.as_bytes()[offset + index],
+                });
+            }
+        }
+
+        let mut warehouse = Warehouse {
+            walls: field.iter().map(|line| vec![false; line.len()]).collect(),
+            ids: field.iter().map(|line| vec![EMPTY; line.len()]).collect(),
+            crates: vec![],
+            robots: vec![],
+            instructions,
+            next: 0,
+            turn: state.turn.1,
+            history: vec![],
+        };
+        for (line, letter, cells) in state.crates {
+            let claimed = cells.iter().all(|&(x, y)| {
+                field.get(y).and_then(|line| line.get(x)) == Some(&letter)
+                    && warehouse.ids[y][x] == EMPTY
+            });
+            if !claimed || cells.iter().collect::<AHashSet<_>>().len() < cells.len() {
+                return Err(WarehouseError::InvalidState { line });
+            }
+            warehouse.add_crate(Shape::Letter(letter), cells);
+        }
+        for (y, line) in field.iter().enumerate() {
+            for (x, &ch) in line.iter().enumerate() {
+                if warehouse.ids[y][x] != EMPTY {
+                    continue;
                 }
-                b'#' => {
-                    new_line.push(b'#');
-                    new_line.push(b'#');
+                match ch {
+                    b'#' => warehouse.walls[y][x] = true,
+                    b'.' => (),
+                    b'@' => {
+                        let multiple = options.multiple_robots || state.robots.is_some();
+                        if !multiple && !warehouse.robots.is_empty() {
+                            return Err(WarehouseError::MultipleRobots { x, y });
+                        }
+                        warehouse.robots.push((x, y))
+                    }
+                    b'O' => warehouse.add_crate(Shape::Single, vec![(x, y)]),
+                    b'[' => {
+                        let length = line[x + 1..]
+                            .iter()
+                            .position(|&ch| ch != b'=')
+                            .filter(|&i| line[x + 1 + i] == b']')
+                            .ok_or(WarehouseError::UnclosedBox { x, y })?;
+                        let cells = (x..x + length + 2).map(|x| (x, y)).collect();
+                        warehouse.add_crate(Shape::Wide, cells);
+                    }
+                    b'a'..=b'z' => {
+                        let cells = Self::letter_cells(&field, x, y);
+                        warehouse.add_crate(Shape::Letter(ch), cells);
+                    }
+                    _ => return Err(WarehouseError::UnknownTile { x, y, tile: ch }),
                 }
-                b'O' => {
-                    new_line.push(b'[');
-                    new_line.push(b']');
+            }
+        }
+        if warehouse.robots.is_empty() {
+            return Err(WarehouseError::MissingRobot);
+        }
+        if let Some((line, robots)) = state.robots {
+            // The listed order replaces scan order, so it must name every
+            // robot on the map exactly once.
+            let mut sorted = robots.clone();
+            sorted.sort_by_key(|&(x, y)| (y, x));
+            if sorted != warehouse.robots {
+                return Err(WarehouseError::InvalidState { line });
+            }
+            warehouse.robots = robots;
+        }
+        if warehouse.turn >= warehouse.robots.len() {
+            return Err(WarehouseError::InvalidState { line: state.turn.0 });
+        }
+        warehouse.check_enclosed()?;
+        Ok(warehouse)
+    }
+
+    // Every open cell needs a neighbor in each direction, so no move can
+    // leave the map.
+    fn check_enclosed(&self) -> Result<(), WarehouseError> {
+        for (y, line) in self.walls.iter().enumerate() {
+            for (x, &wall) in line.iter().enumerate() {
+                if wall {
+                    continue;
                 }
-                b'@' => {
-                    new_line.push(b'@');
-                    new_line.push(b'.');
+                for instruction in [b'^', b'>', b'v', b'<'] {
+                    let inside = shift((x, y), direction(instruction).unwrap())
+                        .is_some_and(|(x, y)| self.walls.get(y).is_some_and(|line| x < line.len()));
+                    if !inside {
+                        return Err(WarehouseError::OpenEdge {
+                            x,
+                            y,
+                            direction: instruction,
+                        });
+                    }
                 }
-                _ => panic!("Unexpected"),
-            }
-        }
-        wide_field.push(new_line);
-    }
-    field = wide_field;
-    x *= 2;
-    for direction in instructions {
-        let Some(dir) = (match direction {
-            b'^' => Some((0, -1)),
-            b'>' => Some((1, 0)),
-            b'v' => Some((0, 1)),
-            b'<' => Some((-1, 0)),
-            _ => None,
-        }) else {
-            continue;
+            }
+        }
+        Ok(())
+    }
+
+    fn letter_cells(field: &[Vec<u8>], x: usize, y: usize) -> Vec<(usize, usize)> {
+        let letter = field[y][x];
+        let mut cells = vec![(x, y)];
+        let mut seen = AHashSet::new();
+        seen.insert((x, y));
+        let mut i = 0;
+        while i < cells.len() {
+            let cell = cells[i];
+            i += 1;
+            for dir in [(0, -1), (1, 0), (0, 1), (-1, 0)] {
+                let Some(next) = shift(cell, dir) else {
+                    continue;
+                };
+                if field.get(next.1).and_then(|line| line.get(next.0)) == Some(&letter)
+                    && seen.insert(next)
+                {
+                    cells.push(next);
+                }
+            }
+        }
+        cells
+    }
+
+    fn add_crate(&mut self, shape: Shape, cells: Vec<(usize, usize)>) {
+        for &(x, y) in &cells {
+            self.ids[y][x] = self.crates.len();
+        }
+        self.crates.push(Crate { shape, cells });
+    }
+
+    pub fn widen(&self, factor: usize) -> Self {
+        let stretch = |line: &Vec<bool>| line.iter().flat_map(|&w| vec![w; factor]).collect();
+        let mut warehouse = Warehouse {
+            walls: self.walls.iter().map(stretch).collect(),
+            ids: self
+                .ids
+                .iter()
+                .map(|line| vec![EMPTY; line.len() * factor])
+                .collect(),
+            crates: vec![],
+            robots: self.robots.iter().map(|&(x, y)| (x * factor, y)).collect(),
+            instructions: self.instructions.clone(),
+            next: self.next,
+            turn: self.turn,
+            history: vec![],
         };
+        for item in &self.crates {
+            let shape = match item.shape {
+                Shape::Single if factor > 1 => Shape::Wide,
+                shape => shape,
+            };
+            let cells = item
+                .cells
+                .iter()
+                .flat_map(|&(x, y)| (x * factor..(x + 1) * factor).map(move |x| (x, y)))
+                .collect();
+            warehouse.add_crate(shape, cells);
+        }
+        warehouse
+    }
 
-        let target = ((x as i32 + dir.0) as usize, (y as i32 + dir.1) as usize);
-        if is_possible_wide_box(&mut field, target.0, target.1, dir) {
-            move_wide_box(&mut field, target.0, target.1, dir);
-            field[target.1][target.0] = b'@';
-            field[y][x] = b'.';
-            x = target.0;
-            y = target.1;
+    pub fn robots(&self) -> &[(usize, usize)] {
+        &self.robots
+    }
+
+    pub fn remaining(&self) -> &[u8] {
+        &self.instructions[self.next..]
+    }
+
+    // Crates that have to shift when the robot moves, or None if something
+    // in the way hits a wall or another robot.
+    fn pushed_crates(&self, robot: usize, dir: (i32, i32)) -> Option<Vec<usize>> {
+        let mut cells = vec![self.robots[robot]];
+        let mut crates = vec![];
+        let mut seen = AHashSet::new();
+        let mut i = 0;
+        while i < cells.len() {
+            let next = shift(cells[i], dir)?;
+            i += 1;
+            if *self.walls.get(next.1)?.get(next.0)? || self.robots.contains(&next) {
+                return None;
+            }
+            let id = self.ids[next.1][next.0];
+            if id != EMPTY && seen.insert(id) {
+                crates.push(id);
+                cells.extend(&self.crates[id].cells);
+            }
         }
+        Some(crates)
     }
-    let mut result = 0;
-    for i in 0..field.len() {
-        for j in 0..field[0].len() {
-            if field[i][j] == b'[' {
-                result += (100 * i + j) as i64;
+
+    fn shift_crates(&mut self, crates: &[usize], dir: (i32, i32)) {
+        for &id in crates {
+            for &(x, y) in &self.crates[id].cells {
+                self.ids[y][x] = EMPTY;
             }
         }
+        for &id in crates {
+            for cell in self.crates[id].cells.iter_mut() {
+                *cell = shift(*cell, dir).unwrap();
+                self.ids[cell.1][cell.0] = id;
+            }
+        }
+    }
+
+    pub fn apply(&mut self, robot: usize, instruction: u8) -> Move {
+        let mut result = Move {
+            robot,
+            direction: instruction,
+            moved: false,
+            boxes: vec![],
+        };
+        let Some(dir) = direction(instruction) else {
+            return result;
+        };
+        let Some(crates) = self.pushed_crates(robot, dir) else {
+            return result;
+        };
+
+        result.boxes = crates.iter().map(|&id| self.crates[id].corner()).collect();
+        result.boxes.sort_by_key(|&(x, y)| (y, x));
+        result.moved = true;
+        self.shift_crates(&crates, dir);
+        self.robots[robot] = shift(self.robots[robot], dir).unwrap();
+        self.history.push(Step {
+            robot,
+            next: self.next,
+            dir,
+            crates,
+        });
+        result
+    }
+
+    pub fn step(&mut self) -> Option<Move> {
+        let instruction = *self.instructions.get(self.next)?;
+        let robot = (self.turn + self.next) % self.robots.len();
+        let result = self.apply(robot, instruction);
+        if !result.moved {
+            self.history.push(Step {
+                robot,
+                next: self.next,
+                dir: (0, 0),
+                crates: vec![],
+            });
+        }
+        self.next += 1;
+        Some(result)
+    }
+
+    pub fn run(&mut self) {
+        while self.step().is_some() {}
+    }
+
+    pub fn undo(&mut self) -> bool {
+        let Some(step) = self.history.pop() else {
+            return false;
+        };
+        let back = (-step.dir.0, -step.dir.1);
+        self.shift_crates(&step.crates, back);
+        self.robots[step.robot] = shift(self.robots[step.robot], back).unwrap();
+        self.next = step.next;
+        true
+    }
+
+    pub fn render(&self) -> String {
+        let mut field = self
+            .walls
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|&wall| if wall { b'#' } else { b'.' })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for &(x, y) in &self.robots {
+            field[y][x] = b'@';
+        }
+        for item in &self.crates {
+            let (left, _) = item.corner();
+            let right = item.cells.iter().map(|cell| cell.0).max().unwrap();
+            for &(x, y) in &item.cells {
+                field[y][x] = match item.shape {
+                    Shape::Single => b'O',
+                    Shape::Wide if x == left => b'[',
+                    Shape::Wide if x == right => b']',
+                    Shape::Wide => b'=',
+                    Shape::Letter(ch) => ch,
+                };
+            }
+        }
+        field
+            .iter()
+            .map(|line| String::from_utf8_lossy(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // The map and the remaining instructions, followed by the robot order,
+    // whose turn is next and the cells of each letter crate when the map alone
+    // would lose them.
+    pub fn save(&self) -> String {
+        let cells = |cells: &[(usize, usize)]| {
+            cells
+                .iter()
+                .map(|(x, y)| format!(" {},{}", x, y))
+                .collect::<String>()
+        };
+        let mut state = vec![];
+        if self.robots.len() > 1 {
+            state.push(format!("robots{}", cells(&self.robots)));
+            state.push(format!(
+                "turn {}",
+                (self.turn + self.next) % self.robots.len()
+            ));
+        }
+        for item in &self.crates {
+            if let Shape::Letter(letter) = item.shape {
+                state.push(format!("crate {}{}", letter as char, cells(&item.cells)));
+            }
+        }
+        let mut saved = format!(
+            "{}\n\n{}",
+            self.render(),
+            String::from_utf8_lossy(self.remaining())
+        );
+        if !state.is_empty() {
+            saved += &format!("\n\n{}", state.join("\n"));
+        }
+        saved
+    }
+
+    pub fn gps(&self) -> i64 {
+        self.crates
+            .iter()
+            .map(|item| {
+                let (x, y) = item.corner();
+                (100 * y + x) as i64
+            })
+            .sum()
     }
-    result
+}
+
+pub fn part1(input: &str) -> i64 {
+    let mut warehouse = Warehouse::parse(input).unwrap();
+    warehouse.run();
+    warehouse.gps()
+}
+
+pub fn part2(input: &str) -> i64 {
+    let mut warehouse = Warehouse::parse(input).unwrap().widen(2);
+    warehouse.run();
+    warehouse.gps()
 }
 
 #[cfg(test)]
@@ -247,4 +642,331 @@ mod tests {
     fn test_day15_part2() {
         assert_eq!(part2(INPUT2), 9021);
     }
+
+    #[test]
+    fn test_day15_steps() {
+        let mut warehouse = Warehouse::parse(INPUT1).unwrap();
+        let initial = warehouse.save();
+
+        let first = warehouse.step().unwrap();
+        assert!(!first.moved);
+        let second = warehouse.step().unwrap();
+        assert!(second.moved && second.boxes.is_empty());
+        assert_eq!(warehouse.robots(), &[(2, 1)]);
+        warehouse.step();
+        warehouse.step();
+        let push = warehouse.step().unwrap();
+        assert_eq!(push.boxes, vec![(4, 1), (5, 1)]);
+        assert_eq!(
+            warehouse.render(),
+            "########\n\
+             #...@OO#\n\
+             ##..O..#\n\
+             #...O..#\n\
+             #.#.O..#\n\
+             #...O..#\n\
+             #......#\n\
+             ########"
+        );
+
+        let saved = warehouse.save();
+        let mut loaded = Warehouse::parse(&saved).unwrap();
+        assert_eq!(loaded.save(), saved);
+        loaded.run();
+        warehouse.run();
+        assert_eq!(loaded.render(), warehouse.render());
+        assert_eq!(warehouse.gps(), 2028);
+
+        while warehouse.undo() {}
+        assert_eq!(warehouse.save(), initial);
+        assert!(warehouse.step().is_some());
+    }
+
+    #[test]
+    fn test_day15_wide_steps() {
+        let input = "
+            #######
+            #...#.#
+            #.....#
+            #..OO@#
+            #..O..#
+            #.....#
+            #######
+
+            <vv<<^^<<^^";
+        let mut warehouse = Warehouse::parse(input).unwrap().widen(2);
+        warehouse.step();
+        warehouse.step();
+        warehouse.step();
+        warehouse.step();
+        warehouse.step();
+        let push = warehouse.step().unwrap();
+        assert_eq!(push.boxes, vec![(5, 3), (7, 3), (6, 4)]);
+        warehouse.run();
+        assert_eq!(
+            warehouse.render(),
+            "##############\n\
+             ##...[].##..##\n\
+             ##...@.[]...##\n\
+             ##....[]....##\n\
+             ##..........##\n\
+             ##..........##\n\
+             ##############"
+        );
+        for _ in 0..6 {
+            warehouse.undo();
+        }
+        assert_eq!(warehouse.robots(), &[(7, 5)]);
+        assert_eq!(warehouse.render().lines().nth(3), Some("##...[][]...##"));
+    }
+
+    #[test]
+    fn test_day15_crate_shapes() {
+        let input = "
+            ########
+            #......#
+            #.O.O..#
+            #..@...#
+            #......#
+            ########
+
+            ^>>>v<<<<^";
+        let mut warehouse = Warehouse::parse(input).unwrap().widen(3);
+        assert_eq!(
+            warehouse.render().lines().nth(2),
+            Some("###...[=]...[=]......###")
+        );
+        for _ in 0..4 {
+            warehouse.step();
+        }
+        assert_eq!(
+            warehouse.render().lines().nth(2),
+            Some("###...[=]...@[=].....###")
+        );
+        warehouse.run();
+        assert_eq!(warehouse.robots(), &[(8, 2)]);
+        assert_eq!(
+            warehouse.render().lines().nth(1),
+            Some("###...[=]............###")
+        );
+        let saved = warehouse.save();
+        assert_eq!(
+            Warehouse::parse(&saved).unwrap().render(),
+            warehouse.render()
+        );
+
+        let input = "
+            #########
+            #.......#
+            #..aa...#
+            #..aab..#
+            #...@b..#
+            #.......#
+            #########
+
+            ^><^";
+        let mut warehouse = Warehouse::parse(input).unwrap();
+        assert_eq!(warehouse.gps(), 203 + 305);
+        let push = warehouse.step().unwrap();
+        assert_eq!(push.boxes, vec![(3, 2)]);
+        assert_eq!(warehouse.step().unwrap().boxes, vec![(5, 3)]);
+        assert!(warehouse.step().unwrap().moved);
+        assert!(!warehouse.step().unwrap().moved);
+        assert_eq!(
+            warehouse.render(),
+            "#########\n\
+             #..aa...#\n\
+             #..aa...#\n\
+             #...@.b.#\n\
+             #.....b.#\n\
+             #.......#\n\
+             #########"
+        );
+        while warehouse.undo() {}
+        assert_eq!(
+            Warehouse::parse(&warehouse.save()).unwrap().save(),
+            warehouse.save()
+        );
+        assert_eq!(warehouse.gps(), 203 + 305);
+    }
+
+    #[test]
+    fn test_day15_robots() {
+        let input = "
+            #######
+            #@O...#
+            #.....#
+            #...O@#
+            #######
+
+            >><<>^";
+        let options = Options {
+            multiple_robots: true,
+            ..Options::default()
+        };
+        let mut warehouse = Warehouse::parse_with(input, options).unwrap();
+        let moves = std::iter::from_fn(|| warehouse.step())
+            .map(|m| (m.robot, m.moved))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            moves,
+            vec![
+                (0, true),
+                (1, false),
+                (0, true),
+                (1, true),
+                (0, true),
+                (1, true)
+            ]
+        );
+        assert_eq!(
+            warehouse.render(),
+            "#######\n\
+             #.@O..#\n\
+             #...@.#\n\
+             #..O..#\n\
+             #######"
+        );
+    }
+
+    #[test]
+    fn test_day15_save_state() {
+        let input = "
+            #######
+            #@....#
+            #.....#
+            #....@#
+            #######
+
+            >><<";
+        let options = Options {
+            multiple_robots: true,
+            ..Options::default()
+        };
+        let mut warehouse = Warehouse::parse_with(input, options).unwrap();
+        warehouse.step();
+        let saved = warehouse.save();
+        assert!(saved.ends_with("\n\n><<\n\nrobots 2,1 5,3\nturn 1"));
+        let mut loaded = Warehouse::parse(&saved).unwrap();
+        assert_eq!(loaded.save(), saved);
+        assert_eq!(loaded.step().unwrap().robot, 1);
+        loaded.run();
+        warehouse.run();
+        assert_eq!(loaded.robots(), warehouse.robots());
+        assert_eq!(loaded.render(), warehouse.render());
+
+        // Robots are listed in their own order, not the order of the map.
+        let swapped = saved.replace("robots 2,1 5,3", "robots 5,3 2,1");
+        assert_eq!(
+            Warehouse::parse(&swapped).unwrap().robots(),
+            &[(5, 3), (2, 1)]
+        );
+
+        // Two crates of the same letter pushed together stay two crates.
+        let input = "
+            #########
+            #.......#
+            #.a.a@..#
+            #.......#
+            #########
+
+            <<^<v";
+        let mut warehouse = Warehouse::parse(input).unwrap();
+        warehouse.step();
+        warehouse.step();
+        assert_eq!(warehouse.render().lines().nth(2), Some("#aa@....#"));
+        let mut loaded = Warehouse::parse(&warehouse.save()).unwrap();
+        assert_eq!(loaded.gps(), warehouse.gps());
+        loaded.run();
+        warehouse.run();
+        assert_eq!(loaded.render(), warehouse.render());
+        assert_eq!(loaded.gps(), 201 + 302);
+
+        let parse =
+            |state: &str| Warehouse::parse(&format!("####\n#@a#\n####\n\n<\n\n{}", state)).err();
+        assert_eq!(parse("crate a 2,1"), None);
+        for (state, line) in [
+            ("robots 1,1 1,1", 7),
+            ("turn 1", 7),
+            ("crate a 1,1", 7),
+            ("crate a 2,1\ncrate a 2,1", 8),
+            ("crate A 2,1", 7),
+            ("crate a", 7),
+            ("boxes 2,1", 7),
+        ] {
+            assert_eq!(parse(state), Some(WarehouseError::InvalidState { line }));
+        }
+    }
+
+    #[test]
+    fn test_day15_errors() {
+        let parse = |input: &str| Warehouse::parse(input).err();
+        assert_eq!(parse("####\n#@.#\n####\n\n<"), None);
+        assert_eq!(
+            parse("####\n#@?#\n####"),
+            Some(WarehouseError::UnknownTile {
+                x: 2,
+                y: 1,
+                tile: b'?'
+            })
+        );
+        assert_eq!(
+            parse("####\n#..#\n####"),
+            Some(WarehouseError::MissingRobot)
+        );
+        assert_eq!(
+            parse("####\n#@@#\n####"),
+            Some(WarehouseError::MultipleRobots { x: 2, y: 1 })
+        );
+        assert_eq!(
+            parse("#####\n#@[=#\n#####"),
+            Some(WarehouseError::UnclosedBox { x: 2, y: 1 })
+        );
+        assert_eq!(
+            parse("####\n#@..\n####"),
+            Some(WarehouseError::OpenEdge {
+                x: 3,
+                y: 1,
+                direction: b'>'
+            })
+        );
+        assert_eq!(
+            parse("####\n#@.#\n##"),
+            Some(WarehouseError::OpenEdge {
+                x: 2,
+                y: 1,
+                direction: b'v'
+            })
+        );
+
+        let input = "####\n#@.#\n####\n\n<>x^";
+        assert!(Warehouse::parse(input).is_ok());
+        let strict = Options {
+            strict: true,
+            ..Options::default()
+        };
+        let error = Warehouse::parse_with(input, strict).err().unwrap();
+        assert_eq!(
+            error,
+            WarehouseError::UnknownInstruction {
+                line: 5,
+                column: 3,
+                instruction: b'x'
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "unknown instruction 'x' at line 5, column 3"
+        );
+
+        let input = "####\n#@.#\n####\n\n<>^\n  v<\n>>?v";
+        assert_eq!(
+            Warehouse::parse_with(input, strict).err(),
+            Some(WarehouseError::UnknownInstruction {
+                line: 7,
+                column: 3,
+                instruction: b'?'
+            })
+        );
+    }
 }
@@ -1,5 +1,45 @@
+use ahash::{AHashMap, AHashSet};
+use std::cmp::Reverse;
 use std::collections::BinaryHeap;
 
+const DIRS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Costs {
+    pub step: i64,
+    pub turn: i64,
+    pub reverse: i64,
+}
+
+impl Default for Costs {
+    fn default() -> Self {
+        Costs {
+            step: 1,
+            turn: 1000,
+            reverse: 2000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Move {
+    Forward,
+    Left,
+    Right,
+    Reverse,
+}
+
+impl Move {
+    pub fn symbol(&self) -> char {
+        match self {
+            Move::Forward => 'F',
+            Move::Left => 'L',
+            Move::Right => 'R',
+            Move::Reverse => 'B',
+        }
+    }
+}
+
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
 struct Position {
     x: usize,
@@ -8,7 +48,12 @@ struct Position {
 }
 
 impl Position {
-    fn step_to_dir(&self, dir: (i32, i32), field: &[Vec<u8>]) -> Vec<(Position, i64)> {
+    fn step_to_dir(
+        &self,
+        dir: (i32, i32),
+        field: &[Vec<u8>],
+        costs: &Costs,
+    ) -> Vec<(Position, i64, Move)> {
         let new_x = (self.x as i32 + dir.0) as usize;
         let new_y = (self.y as i32 + dir.1) as usize;
         let mut result = vec![];
@@ -19,34 +64,48 @@ impl Position {
                     y: new_y,
                     dir: self.dir,
                 },
-                1,
+                costs.step,
+                Move::Forward,
             ));
         }
+        result.push((
+            Position {
+                x: self.x,
+                y: self.y,
+                dir: (self.dir.1, -self.dir.0),
+            },
+            costs.turn,
+            Move::Left,
+        ));
         result.push((
             Position {
                 x: self.x,
                 y: self.y,
                 dir: (-self.dir.1, self.dir.0),
             },
-            1000,
+            costs.turn,
+            Move::Right,
         ));
         result.push((
             Position {
                 x: self.x,
                 y: self.y,
-                dir: (self.dir.1, -self.dir.0),
+                dir: (-self.dir.0, -self.dir.1),
             },
-            1000,
+            costs.reverse,
+            Move::Reverse,
         ));
         result
     }
 
-    fn step(&self, field: &[Vec<u8>]) -> Vec<(Position, i64)> {
-        self.step_to_dir(self.dir, field)
+    fn step(&self, field: &[Vec<u8>], costs: &Costs) -> Vec<(Position, i64, Move)> {
+        self.step_to_dir(self.dir, field, costs)
     }
 
-    fn step_back(&self, field: &[Vec<u8>]) -> Vec<(Position, i64)> {
-        self.step_to_dir((-self.dir.0, -self.dir.1), field)
+    // Transitions are symmetric: stepping back or undoing a turn costs the
+    // same as the move itself, with left and right swapped.
+    fn step_back(&self, field: &[Vec<u8>], costs: &Costs) -> Vec<(Position, i64, Move)> {
+        self.step_to_dir((-self.dir.0, -self.dir.1), field, costs)
     }
 }
 
@@ -94,123 +153,453 @@ fn parse(input: &str) -> (Vec<Vec<u8>>, Position, Position) {
     (field, start, end)
 }
 
-fn heuristic(position: &Position, end: &Position) -> i64 {
-    (end.x.abs_diff(position.x)
-        + end.y.abs_diff(position.y)
-        + if end.x != position.x && end.y != position.y {
-            1000
-        } else {
-            0
-        }) as i64
+// Exact cost to the end in the same maze with every wall removed, so it is
+// admissible and consistent for any non-negative costs. The reindeer needs a
+// step per tile of Manhattan distance and has to face every direction it
+// moves in at least once.
+fn heuristic(position: &Position, end: &Position, costs: &Costs) -> i64 {
+    let (dx, dy) = (
+        end.x as i64 - position.x as i64,
+        end.y as i64 - position.y as i64,
+    );
+    let needed = [(dx.signum() as i32, 0), (0, dy.signum() as i32)];
+    let needed = needed
+        .iter()
+        .filter(|dir| **dir != (0, 0))
+        .collect::<Vec<_>>();
+    let quarter = costs.turn;
+    let half = costs.reverse.min(2 * costs.turn);
+    let facing = |dir: &(i32, i32)| *dir == position.dir;
+    let opposite = |dir: &(i32, i32)| (-dir.0, -dir.1) == position.dir;
+    let turns = match needed.as_slice() {
+        [] => 0,
+        [dir] if facing(dir) => 0,
+        [dir] if opposite(dir) => half,
+        [_] => quarter,
+        [a, b] if facing(a) || facing(b) => quarter,
+        _ => quarter + quarter.min(half),
+    };
+    (dx.abs() + dy.abs()) * costs.step + turns
 }
 
+// A* over (x, y, dir). Keeps expanding until every state that could lie on an
+// optimal path is settled, so `visited` holds exact distances for them.
 fn find_record(
     field: &[Vec<u8>],
     start: Position,
     end: Position,
+    costs: &Costs,
     visited: &mut [Vec<[i64; 4]>],
 ) -> i64 {
-    let mut heap: BinaryHeap<(i64, i64, Position)> = BinaryHeap::with_capacity(1024);
+    let mut heap: BinaryHeap<Reverse<(i64, i64, Position)>> = BinaryHeap::with_capacity(1024);
     let mut result = i64::MAX;
     visited[start.y][start.x][dir_to_index(start.dir)] = 0;
-    heap.push((heuristic(&start, &end), 0, start));
-    while let Some((_, current_points, position)) = heap.pop() {
-        if current_points >= result {
+    heap.push(Reverse((heuristic(&start, &end, costs), 0, start)));
+    while let Some(Reverse((estimate, current_points, position))) = heap.pop() {
+        if estimate > result {
+            break;
+        }
+        if current_points > visited[position.y][position.x][dir_to_index(position.dir)] {
+            continue;
+        }
+        if position.x == end.x && position.y == end.y {
+            result = result.min(current_points);
             continue;
         }
-        for (next_position, points) in position.step(field) {
+        for (next_position, points, _) in position.step(field, costs) {
             let new_points = current_points + points;
-            if new_points >= result {
-                continue;
-            }
-            if next_position.x == end.x && next_position.y == end.y {
-                result = result.min(new_points);
-            }
             let prev_points =
-                visited[next_position.y][next_position.x][dir_to_index(next_position.dir)];
-            if prev_points > new_points {
-                visited[next_position.y][next_position.x][dir_to_index(next_position.dir)] =
-                    new_points;
-                heap.push((
-                    -heuristic(&next_position, &end) - new_points,
+                &mut visited[next_position.y][next_position.x][dir_to_index(next_position.dir)];
+            if *prev_points > new_points {
+                *prev_points = new_points;
+                heap.push(Reverse((
+                    new_points + heuristic(&next_position, &end, costs),
                     new_points,
                     next_position,
-                ));
+                )));
             }
         }
     }
     result
 }
 
-fn find_path(field: &[Vec<u8>], end: Position, visited: &[Vec<[i64; 4]>], record: i64) -> i64 {
-    let mut paths: Vec<Vec<i64>> = vec![vec![0i64; field[0].len()]; field.len()];
-    let mut points = vec![
-        (
-            Position {
-                x: end.x,
-                y: end.y,
-                dir: (0, 1),
-            },
-            record,
-        ),
-        (
-            Position {
-                x: end.x,
-                y: end.y,
-                dir: (0, -1),
-            },
-            record,
-        ),
-        (
-            Position {
-                x: end.x,
-                y: end.y,
-                dir: (1, 0),
-            },
-            record,
-        ),
-        (
-            Position {
-                x: end.x,
-                y: end.y,
-                dir: (-1, 0),
-            },
-            record,
-        ),
-    ];
-    paths[end.y][end.x] = 1;
+// Marks every state that lies on some optimal path by walking back from the
+// end along transitions that are tight with respect to `visited`.
+fn find_path(
+    field: &[Vec<u8>],
+    end: Position,
+    costs: &Costs,
+    visited: &[Vec<[i64; 4]>],
+    record: i64,
+) -> Vec<Vec<[bool; 4]>> {
+    let mut on_path = vec![vec![[false; 4]; field[0].len()]; field.len()];
+    let mut points = DIRS
+        .iter()
+        .map(|&dir| {
+            (
+                Position {
+                    x: end.x,
+                    y: end.y,
+                    dir,
+                },
+                record,
+            )
+        })
+        .filter(|(position, value)| visited[end.y][end.x][dir_to_index(position.dir)] == *value)
+        .collect::<Vec<_>>();
+    for (position, _) in &points {
+        on_path[end.y][end.x][dir_to_index(position.dir)] = true;
+    }
     while let Some((position, value)) = points.pop() {
-        for (prev_position, points_change) in position.step_back(field) {
-            if visited[prev_position.y][prev_position.x][dir_to_index(prev_position.dir)]
-                == value - points_change
+        for (prev_position, points_change, _) in position.step_back(field, costs) {
+            let index = dir_to_index(prev_position.dir);
+            if visited[prev_position.y][prev_position.x][index] == value - points_change
+                && !on_path[prev_position.y][prev_position.x][index]
             {
-                paths[prev_position.y][prev_position.x] = 1;
+                on_path[prev_position.y][prev_position.x][index] = true;
                 points.push((prev_position, value - points_change));
             }
         }
     }
-    paths
-        .into_iter()
-        .map(|line| line.into_iter().sum::<i64>())
-        .sum::<i64>()
+    on_path
+}
+
+pub struct Solution<'a> {
+    pub score: i64,
+    pub tiles: Vec<(usize, usize)>,
+    maze: &'a Maze,
+    visited: Vec<Vec<[i64; 4]>>,
+    on_path: Vec<Vec<[bool; 4]>>,
+}
+
+impl Solution<'_> {
+    // Every optimal path as a move sequence. There can be exponentially many,
+    // so they are found one at a time.
+    pub fn paths(&self) -> OptimalPaths<'_> {
+        OptimalPaths {
+            solution: self,
+            stack: vec![],
+            moves: vec![],
+            started: false,
+        }
+    }
+
+    // Steps out of `position` that stay on an optimal path, last one first.
+    fn tight_steps(&self, position: Position) -> Vec<(Position, Move)> {
+        let points = self.visited[position.y][position.x][dir_to_index(position.dir)];
+        let mut steps = position
+            .step(&self.maze.field, &self.maze.costs)
+            .into_iter()
+            .filter(|&(next, cost, _)| {
+                let index = dir_to_index(next.dir);
+                self.on_path[next.y][next.x][index]
+                    && self.visited[next.y][next.x][index] == points + cost
+            })
+            .map(|(next, _, step)| (next, step))
+            .collect::<Vec<_>>();
+        steps.reverse();
+        steps
+    }
+}
+
+// Depth-first walk over the tight transitions, yielding a path whenever it
+// reaches the end.
+pub struct OptimalPaths<'a> {
+    solution: &'a Solution<'a>,
+    stack: Vec<(Position, Vec<(Position, Move)>)>,
+    moves: Vec<Move>,
+    started: bool,
+}
+
+impl Iterator for OptimalPaths<'_> {
+    type Item = Vec<Move>;
+
+    fn next(&mut self) -> Option<Vec<Move>> {
+        let maze = self.solution.maze;
+        if !self.started {
+            self.started = true;
+            if maze.is_end(&maze.start) {
+                self.stack.push((maze.start, vec![]));
+                return Some(vec![]);
+            }
+            self.stack
+                .push((maze.start, self.solution.tight_steps(maze.start)));
+        }
+        loop {
+            let (_, steps) = self.stack.last_mut()?;
+            let Some((next, step)) = steps.pop() else {
+                self.stack.pop();
+                self.moves.pop();
+                continue;
+            };
+            if self.stack.iter().any(|&(position, _)| position == next) {
+                continue;
+            }
+            self.moves.push(step);
+            if maze.is_end(&next) {
+                self.stack.push((next, vec![]));
+                return Some(self.moves.clone());
+            }
+            self.stack.push((next, self.solution.tight_steps(next)));
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub score: i64,
+    pub moves: Vec<Move>,
+}
+
+#[derive(Clone)]
+struct StatePath {
+    nodes: Vec<Position>,
+    moves: Vec<Move>,
+    points: Vec<i64>,
+}
+
+impl StatePath {
+    fn score(&self) -> i64 {
+        *self.points.last().unwrap()
+    }
+}
+
+pub struct Maze {
+    field: Vec<Vec<u8>>,
+    start: Position,
+    end: Position,
+    pub costs: Costs,
+}
+
+impl Maze {
+    pub fn new(input: &str, costs: Costs) -> Self {
+        let (field, start, end) = parse(input);
+        Maze {
+            field,
+            start,
+            end,
+            costs,
+        }
+    }
+
+    fn distances(&self) -> (Vec<Vec<[i64; 4]>>, i64) {
+        let mut visited = vec![vec![[i64::MAX; 4]; self.field[0].len()]; self.field.len()];
+        let record = find_record(&self.field, self.start, self.end, &self.costs, &mut visited);
+        (visited, record)
+    }
+
+    pub fn best_score(&self) -> Option<i64> {
+        Some(self.distances().1).filter(|&record| record != i64::MAX)
+    }
+
+    // The optimal score and the tiles on any optimal path; the paths
+    // themselves are enumerated lazily by `Solution::paths`.
+    pub fn solve(&self) -> Option<Solution<'_>> {
+        let (visited, record) = self.distances();
+        if record == i64::MAX {
+            return None;
+        }
+        let on_path = find_path(&self.field, self.end, &self.costs, &visited, record);
+
+        let mut tiles = vec![];
+        for (y, line) in on_path.iter().enumerate() {
+            for (x, states) in line.iter().enumerate() {
+                if states.iter().any(|&state| state) {
+                    tiles.push((x, y));
+                }
+            }
+        }
+
+        Some(Solution {
+            score: record,
+            tiles,
+            maze: self,
+            visited,
+            on_path,
+        })
+    }
+
+    fn is_end(&self, position: &Position) -> bool {
+        position.x == self.end.x && position.y == self.end.y
+    }
+
+    // Plain Dijkstra from `from` to any end state that avoids the given
+    // states and transitions.
+    fn shortest_from(
+        &self,
+        from: Position,
+        banned_nodes: &AHashSet<Position>,
+        banned_edges: &AHashSet<(Position, Position)>,
+    ) -> Option<StatePath> {
+        let mut best: AHashMap<Position, (i64, Option<(Position, Move)>)> = AHashMap::new();
+        let mut heap = BinaryHeap::new();
+        best.insert(from, (0, None));
+        heap.push(Reverse((0, from)));
+        while let Some(Reverse((points, position))) = heap.pop() {
+            if best[&position].0 < points {
+                continue;
+            }
+            if self.is_end(&position) {
+                let mut path = StatePath {
+                    nodes: vec![position],
+                    moves: vec![],
+                    points: vec![points],
+                };
+                let mut current = position;
+                while let Some((prev, step)) = best[&current].1 {
+                    path.nodes.push(prev);
+                    path.moves.push(step);
+                    path.points.push(best[&prev].0);
+                    current = prev;
+                }
+                path.nodes.reverse();
+                path.moves.reverse();
+                path.points.reverse();
+                return Some(path);
+            }
+            for (next_position, cost, step) in position.step(&self.field, &self.costs) {
+                if banned_nodes.contains(&next_position)
+                    || banned_edges.contains(&(position, next_position))
+                {
+                    continue;
+                }
+                let new_points = points + cost;
+                if best
+                    .get(&next_position)
+                    .is_none_or(|&(prev_points, _)| new_points < prev_points)
+                {
+                    best.insert(next_position, (new_points, Some((position, step))));
+                    heap.push(Reverse((new_points, next_position)));
+                }
+            }
+        }
+        None
+    }
+
+    // Every simple route over the (x, y, dir) state graph, cheapest first.
+    pub fn routes(&self) -> Routes<'_> {
+        Routes {
+            maze: self,
+            found: vec![],
+            candidates: vec![],
+            started: false,
+        }
+    }
+
+    pub fn k_best_routes(&self, k: usize) -> Vec<Route> {
+        self.routes().take(k).collect()
+    }
+
+    // Every simple route that costs at most `budget` more than the optimum,
+    // cheapest first, capped at `limit` routes.
+    pub fn routes_within(&self, budget: i64, limit: usize) -> Vec<Route> {
+        let mut routes = self.routes().peekable();
+        let Some(best) = routes.peek().map(|route| route.score) else {
+            return vec![];
+        };
+        let mut routes = routes
+            .take(limit)
+            .take_while(|route| route.score <= best + budget)
+            .collect::<Vec<_>>();
+        routes.sort_by(|a: &Route, b: &Route| a.score.cmp(&b.score).then(a.moves.cmp(&b.moves)));
+        routes
+    }
+
+    pub fn render(&self, tiles: &[(usize, usize)]) -> String {
+        let mut field = self.field.clone();
+        for &(x, y) in tiles {
+            field[y][x] = b'O';
+        }
+        field
+            .iter()
+            .map(|line| String::from_utf8_lossy(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+// Yen's algorithm, producing one more route on every call.
+pub struct Routes<'a> {
+    maze: &'a Maze,
+    found: Vec<StatePath>,
+    candidates: Vec<StatePath>,
+    started: bool,
+}
+
+impl Iterator for Routes<'_> {
+    type Item = Route;
+
+    fn next(&mut self) -> Option<Route> {
+        if !self.started {
+            self.started = true;
+            let path =
+                self.maze
+                    .shortest_from(self.maze.start, &AHashSet::new(), &AHashSet::new())?;
+            self.found.push(path);
+        } else {
+            let last = self.found.last()?.clone();
+            for i in 0..last.nodes.len() - 1 {
+                let spur = last.nodes[i];
+                let root = &last.nodes[..=i];
+                let banned_edges = self
+                    .found
+                    .iter()
+                    .filter(|path| path.nodes.len() > i + 1 && path.nodes[..=i] == *root)
+                    .map(|path| (path.nodes[i], path.nodes[i + 1]))
+                    .collect::<AHashSet<_>>();
+                let banned_nodes = root[..i].iter().copied().collect::<AHashSet<_>>();
+                let Some(spur_path) = self.maze.shortest_from(spur, &banned_nodes, &banned_edges)
+                else {
+                    continue;
+                };
+                let mut path = StatePath {
+                    nodes: root[..i].to_vec(),
+                    moves: last.moves[..i].to_vec(),
+                    points: last.points[..i].to_vec(),
+                };
+                path.nodes.extend(spur_path.nodes);
+                path.moves.extend(spur_path.moves);
+                path.points.extend(
+                    spur_path
+                        .points
+                        .iter()
+                        .map(|points| points + last.points[i]),
+                );
+                if !self.candidates.iter().any(|c| c.nodes == path.nodes)
+                    && !self.found.iter().any(|f| f.nodes == path.nodes)
+                {
+                    self.candidates.push(path);
+                }
+            }
+            let index = (0..self.candidates.len()).min_by_key(|&i| self.candidates[i].score())?;
+            let path = self.candidates.swap_remove(index);
+            self.found.push(path);
+        }
+        let path = self.found.last()?;
+        Some(Route {
+            score: path.score(),
+            moves: path.moves.clone(),
+        })
+    }
 }
 
 pub fn part1(input: &str) -> i64 {
-    let (field, start, end) = parse(input);
-    let mut visited = vec![vec![[i64::MAX; 4]; field[0].len()]; field.len()];
-    find_record(&field, start, end, &mut visited)
+    Maze::new(input, Costs::default()).best_score().unwrap()
 }
 
 pub fn part2(input: &str) -> i64 {
-    let (field, start, end) = parse(input);
-    let mut visited = vec![vec![[i64::MAX; 4]; field[0].len()]; field.len()];
-    let record = find_record(&field, start, end, &mut visited);
-    find_path(&field, end, &visited, record)
+    Maze::new(input, Costs::default())
+        .solve()
+        .unwrap()
+        .tiles
+        .len() as i64
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
     const INPUT1: &str = "
         ###############
@@ -261,4 +650,320 @@ mod tests {
         assert_eq!(part2(INPUT1), 45);
         assert_eq!(part2(INPUT2), 64);
     }
+
+    fn score(moves: &[Move], costs: &Costs) -> i64 {
+        moves
+            .iter()
+            .map(|step| match step {
+                Move::Forward => costs.step,
+                Move::Left | Move::Right => costs.turn,
+                Move::Reverse => costs.reverse,
+            })
+            .sum()
+    }
+
+    #[test]
+    fn test_day16_solution() {
+        let maze = Maze::new(INPUT1, Costs::default());
+        let solution = maze.solve().unwrap();
+        assert_eq!(solution.score, 7036);
+        assert_eq!(solution.tiles.len(), 45);
+        assert_eq!(solution.paths().count(), 3);
+        for path in solution.paths() {
+            assert_eq!(score(&path, &maze.costs), 7036);
+        }
+        let rendered = maze.render(&solution.tiles);
+        assert_eq!(rendered.lines().nth(1), Some("#.......#....O#"));
+        assert_eq!(rendered.lines().nth(13), Some("#O..#.....#OOO#"));
+
+        let maze = Maze::new(INPUT2, Costs::default());
+        let solution = maze.solve().unwrap();
+        assert_eq!((solution.score, solution.tiles.len()), (11048, 64));
+        assert_eq!(solution.paths().count(), 2);
+    }
+
+    #[test]
+    fn test_day16_costs() {
+        let free_turns = Costs {
+            step: 1,
+            turn: 0,
+            reverse: 0,
+        };
+        let maze = Maze::new(INPUT1, free_turns);
+        assert_eq!(maze.best_score(), Some(28));
+
+        let input = "
+            #####
+            #.#E#
+            #S..#
+            #####";
+        let costs = Costs {
+            step: 10,
+            turn: 1,
+            reverse: 5,
+        };
+        let maze = Maze::new(input, costs);
+        let solution = maze.solve().unwrap();
+        assert_eq!(solution.score, 31);
+        assert_eq!(
+            solution.paths().collect::<Vec<_>>(),
+            vec![vec![
+                Move::Forward,
+                Move::Forward,
+                Move::Left,
+                Move::Forward
+            ]]
+        );
+
+        let input = "
+            #####
+            #E.S#
+            #####";
+        let costs = Costs {
+            step: 1,
+            turn: 1000,
+            reverse: 10,
+        };
+        let maze = Maze::new(input, costs);
+        let solution = maze.solve().unwrap();
+        assert_eq!(solution.score, 12);
+        assert_eq!(
+            solution.paths().collect::<Vec<_>>(),
+            vec![vec![Move::Reverse, Move::Forward, Move::Forward]]
+        );
+        assert_eq!(solution.tiles, vec![(1, 1), (2, 1), (3, 1)]);
+
+        // Free turns in an open room give too many optimal paths to list, but
+        // the tiles and the first few paths come back right away.
+        let room = "
+            ##########
+            #.......E#
+            #........#
+            #........#
+            #........#
+            #........#
+            #........#
+            #........#
+            #S.......#
+            ##########";
+        let maze = Maze::new(room, free_turns);
+        let solution = maze.solve().unwrap();
+        assert_eq!((solution.score, solution.tiles.len()), (14, 64));
+        for path in solution.paths().take(1000) {
+            assert_eq!(score(&path, &maze.costs), 14);
+        }
+    }
+
+    #[test]
+    fn test_day16_k_best() {
+        let maze = Maze::new(INPUT1, Costs::default());
+        let routes = maze.k_best_routes(5);
+        assert_eq!(routes.len(), 5);
+        assert_eq!(
+            routes.iter().map(|route| route.score).collect::<Vec<_>>()[..3],
+            [7036, 7036, 7036]
+        );
+        assert!(routes[3].score > 7036);
+        assert!(routes.windows(2).all(|w| w[0].score <= w[1].score));
+        for route in &routes {
+            assert_eq!(score(&route.moves, &maze.costs), route.score);
+        }
+        let distinct = routes
+            .iter()
+            .map(|route| &route.moves)
+            .collect::<AHashSet<_>>();
+        assert_eq!(distinct.len(), 5);
+
+        let optimal = maze.solve().unwrap().paths().collect::<Vec<_>>();
+        let mut best = routes[..3]
+            .iter()
+            .map(|route| route.moves.clone())
+            .collect::<Vec<_>>();
+        best.sort();
+        let mut expected = optimal.clone();
+        expected.sort();
+        assert_eq!(best, expected);
+    }
+
+    #[test]
+    fn test_day16_within_budget() {
+        let maze = Maze::new(INPUT1, Costs::default());
+        assert_eq!(maze.routes_within(0, 100).len(), 3);
+
+        let routes = maze.routes_within(2000, 1000);
+        assert_eq!(maze.routes_within(2000, 2).len(), 2);
+        assert_eq!(
+            routes.iter().map(|route| route.score).collect::<Vec<_>>(),
+            all_routes(&maze, 7036 + 2000)
+        );
+
+        // The limit bounds the work, not only the output.
+        let routes = maze.routes_within(20_000, 5);
+        assert_eq!(routes.len(), 5);
+        assert!(routes.windows(2).all(|w| w[0].score <= w[1].score));
+    }
+
+    #[test]
+    fn test_day16_unreachable_routes() {
+        let maze = Maze::new("#####\n#S#E#\n#####", Costs::default());
+        assert_eq!(maze.k_best_routes(2), vec![]);
+        assert_eq!(maze.routes_within(1000, 10), vec![]);
+        assert_eq!(maze.routes().next(), None);
+    }
+
+    fn distances_to_end(maze: &Maze) -> AHashMap<Position, i64> {
+        let mut best = AHashMap::new();
+        let mut heap = BinaryHeap::new();
+        for dir in DIRS {
+            let position = Position {
+                x: maze.end.x,
+                y: maze.end.y,
+                dir,
+            };
+            best.insert(position, 0);
+            heap.push(Reverse((0, position)));
+        }
+        while let Some(Reverse((points, position))) = heap.pop() {
+            if best[&position] < points {
+                continue;
+            }
+            for (prev_position, cost, _) in position.step_back(&maze.field, &maze.costs) {
+                let new_points = points + cost;
+                if maze.is_end(&prev_position) {
+                    continue;
+                }
+                if best
+                    .get(&prev_position)
+                    .is_none_or(|&prev_points| new_points < prev_points)
+                {
+                    best.insert(prev_position, new_points);
+                    heap.push(Reverse((new_points, prev_position)));
+                }
+            }
+        }
+        best
+    }
+
+    // Depth-first enumeration of every simple route under `max_points`.
+    fn all_routes(maze: &Maze, max_points: i64) -> Vec<i64> {
+        let to_end = distances_to_end(maze);
+        let mut scores = vec![];
+        let mut stack = vec![(vec![maze.start], 0)];
+        while let Some((path, points)) = stack.pop() {
+            let position = *path.last().unwrap();
+            if maze.is_end(&position) {
+                scores.push(points);
+                continue;
+            }
+            for (next, cost, _) in position.step(&maze.field, &maze.costs) {
+                if to_end
+                    .get(&next)
+                    .is_some_and(|&rest| points + cost + rest <= max_points)
+                    && !path.contains(&next)
+                {
+                    let mut path = path.clone();
+                    path.push(next);
+                    stack.push((path, points + cost));
+                }
+            }
+        }
+        scores.sort();
+        scores
+    }
+
+    fn random_maze(rng: &mut StdRng, width: usize, height: usize) -> String {
+        let mut field = vec![vec![b'.'; width]; height];
+        for (y, line) in field.iter_mut().enumerate() {
+            for (x, cell) in line.iter_mut().enumerate() {
+                if x == 0 || y == 0 || x == width - 1 || y == height - 1 || rng.gen_bool(0.3) {
+                    *cell = b'#';
+                }
+            }
+        }
+        let mut place = |ch| loop {
+            let (x, y) = (rng.gen_range(1..width - 1), rng.gen_range(1..height - 1));
+            if field[y][x] != b'S' {
+                field[y][x] = ch;
+                break;
+            }
+        };
+        place(b'S');
+        place(b'E');
+        field
+            .iter()
+            .map(|line| String::from_utf8_lossy(line).to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_day16_heuristic() {
+        let mut rng = StdRng::seed_from_u64(16);
+        for _ in 0..300 {
+            let (width, height) = (rng.gen_range(4..14), rng.gen_range(4..14));
+            let input = random_maze(&mut rng, width, height);
+            let costs = Costs {
+                step: rng.gen_range(0..20),
+                turn: rng.gen_range(0..3000),
+                reverse: rng.gen_range(0..5000),
+            };
+            let maze = Maze::new(&input, costs);
+
+            let mut positions = vec![];
+            for y in 1..height - 1 {
+                for x in 1..width - 1 {
+                    if maze.field[y][x] != b'#' {
+                        positions.extend(DIRS.iter().map(|&dir| Position { x, y, dir }));
+                    }
+                }
+            }
+            for position in &positions {
+                if maze.is_end(position) {
+                    assert_eq!(heuristic(position, &maze.end, &costs), 0);
+                    continue;
+                }
+                for (next, cost, _) in position.step(&maze.field, &costs) {
+                    let h = heuristic(position, &maze.end, &costs);
+                    assert!(h <= cost + heuristic(&next, &maze.end, &costs));
+                }
+            }
+
+            let dijkstra = maze
+                .shortest_from(maze.start, &AHashSet::new(), &AHashSet::new())
+                .map(|path| path.score());
+            assert_eq!(maze.best_score(), dijkstra, "{}\n{:?}", input, costs);
+
+            if let Some(score) = dijkstra {
+                let (visited, _) = maze.distances();
+                let on_path = find_path(&maze.field, maze.end, &costs, &visited, score);
+                let to_end = distances_to_end(&maze);
+                let mut tiles = AHashSet::new();
+                let mut stack = vec![(maze.start, 0)];
+                let mut seen = AHashSet::new();
+                while let Some((position, points)) = stack.pop() {
+                    if !seen.insert(position) {
+                        continue;
+                    }
+                    tiles.insert((position.x, position.y));
+                    if maze.is_end(&position) {
+                        continue;
+                    }
+                    for (next, cost, _) in position.step(&maze.field, &costs) {
+                        if to_end
+                            .get(&next)
+                            .is_some_and(|&rest| points + cost + rest == score)
+                        {
+                            stack.push((next, points + cost));
+                        }
+                    }
+                }
+                for (y, line) in on_path.iter().enumerate() {
+                    for (x, states) in line.iter().enumerate() {
+                        let tile = states.iter().any(|&state| state);
+                        assert_eq!(tile, tiles.contains(&(x, y)), "{}\n{:?}", input, costs);
+                    }
+                }
+            }
+        }
+    }
 }
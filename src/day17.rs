@@ -1,8 +1,60 @@
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mode {
+    // Reserved combo operand 7 reads as the literal 7, unknown opcodes are
+    // skipped, negative shifts shift by nothing and jumps past the end halt.
+    #[default]
+    Lenient,
+    Strict,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VmError {
+    ReservedOperand { pointer: usize },
+    UnknownOpcode { pointer: usize, opcode: i64 },
+    NegativeShift { pointer: usize, amount: i64 },
+    JumpOutOfRange { pointer: usize, target: i64 },
+    StepLimit { steps: usize },
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            VmError::ReservedOperand { pointer } => {
+                write!(f, "reserved combo operand 7 at {}", pointer)
+            }
+            VmError::UnknownOpcode { pointer, opcode } => {
+                write!(f, "unknown opcode {} at {}", opcode, pointer)
+            }
+            VmError::NegativeShift { pointer, amount } => {
+                write!(f, "negative shift {} at {}", amount, pointer)
+            }
+            VmError::JumpOutOfRange { pointer, target } => {
+                write!(f, "jump to {} at {} leaves the program", target, pointer)
+            }
+            VmError::StepLimit { steps } => write!(f, "step limit of {} reached", steps),
+        }
+    }
+}
+
+impl std::error::Error for VmError {}
+
 struct Computer {
     a: i64,
     b: i64,
     c: i64,
     pointer: usize,
+    mode: Mode,
+}
+
+// `value / 2^amount` without overflowing for large amounts.
+fn shift(value: i64, amount: i64) -> i64 {
+    if amount >= 64 {
+        0
+    } else {
+        (value as i128 / (1i128 << amount)) as i64
+    }
 }
 
 impl Computer {
@@ -12,69 +64,442 @@ impl Computer {
             b,
             c,
             pointer: 0,
+            mode: Mode::Lenient,
         }
     }
 
-    fn combo(&self, operand: i64) -> i64 {
+    fn combo(&self, operand: i64) -> Result<i64, VmError> {
         match operand {
-            4 => self.a,
-            5 => self.b,
-            6 => self.c,
-            _ => operand,
+            4 => Ok(self.a),
+            5 => Ok(self.b),
+            6 => Ok(self.c),
+            7 if self.mode == Mode::Strict => Err(VmError::ReservedOperand {
+                pointer: self.pointer,
+            }),
+            _ => Ok(operand),
         }
     }
 
-    fn execute(&mut self, program: &[i64]) -> Vec<i64> {
-        let mut output = vec![];
-        while self.pointer < program.len() - 1 {
-            match program[self.pointer] {
-                0 => {
-                    let operand = self.combo(program[self.pointer + 1]);
-                    self.a /= 2i64.pow(operand as u32);
-                    self.pointer += 2;
-                }
-                1 => {
-                    let operand = program[self.pointer + 1];
-                    self.b ^= operand;
-                    self.pointer += 2;
-                }
-                2 => {
-                    let operand = self.combo(program[self.pointer + 1]);
-                    self.b = operand % 8;
-                    self.pointer += 2;
-                }
-                3 => {
-                    if self.a != 0 {
-                        let operand = program[self.pointer + 1];
-                        self.pointer = operand as usize;
-                    } else {
-                        self.pointer += 2;
+    fn divide(&self, operand: i64) -> Result<i64, VmError> {
+        let amount = self.combo(operand)?;
+        match (amount < 0, self.mode) {
+            (true, Mode::Strict) => Err(VmError::NegativeShift {
+                pointer: self.pointer,
+                amount,
+            }),
+            (true, Mode::Lenient) => Ok(self.a),
+            (false, _) => Ok(shift(self.a, amount)),
+        }
+    }
+
+    fn step(&mut self, program: &[i64]) -> Result<Option<Option<i64>>, VmError> {
+        if self.pointer + 1 >= program.len() {
+            return Ok(None);
+        }
+        let operand = program[self.pointer + 1];
+        let mut output = None;
+        match program[self.pointer] {
+            0 => self.a = self.divide(operand)?,
+            1 => self.b ^= operand,
+            2 => self.b = self.combo(operand)? % 8,
+            3 => {
+                if self.a != 0 {
+                    if self.mode == Mode::Strict
+                        && (operand < 0 || operand as usize + 1 >= program.len())
+                    {
+                        return Err(VmError::JumpOutOfRange {
+                            pointer: self.pointer,
+                            target: operand,
+                        });
                     }
+                    self.pointer = usize::try_from(operand).unwrap_or(usize::MAX - 1);
+                    return Ok(Some(None));
                 }
-                4 => {
-                    self.b ^= self.c;
-                    self.pointer += 2;
+            }
+            4 => self.b ^= self.c,
+            5 => output = Some(self.combo(operand)? % 8),
+            6 => self.b = self.divide(operand)?,
+            7 => self.c = self.divide(operand)?,
+            opcode => {
+                if self.mode == Mode::Strict {
+                    return Err(VmError::UnknownOpcode {
+                        pointer: self.pointer,
+                        opcode,
+                    });
                 }
-                5 => {
-                    let operand = self.combo(program[self.pointer + 1]);
-                    output.push(operand % 8);
-                    self.pointer += 2;
+            }
+        }
+        self.pointer += 2;
+        Ok(Some(output))
+    }
+
+    fn run(&mut self, program: &[i64], step_limit: Option<usize>) -> Result<Vec<i64>, VmError> {
+        let mut output = vec![];
+        let mut steps = 0;
+        while let Some(value) = self.step(program)? {
+            output.extend(value);
+            steps += 1;
+            if step_limit.is_some_and(|limit| steps >= limit) && self.pointer + 1 < program.len() {
+                return Err(VmError::StepLimit { steps });
+            }
+        }
+        Ok(output)
+    }
+
+    fn execute(&mut self, program: &[i64]) -> Vec<i64> {
+        self.run(program, None).unwrap()
+    }
+}
+
+pub fn run(
+    program: &[i64],
+    registers: [i64; 3],
+    mode: Mode,
+    step_limit: Option<usize>,
+) -> Result<Vec<i64>, VmError> {
+    let mut computer = Computer::new(registers[0], registers[1], registers[2]);
+    computer.mode = mode;
+    computer.run(program, step_limit)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Register {
+    A,
+    B,
+    C,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Halted,
+    Breakpoint(usize),
+    Watch {
+        register: Register,
+        old: i64,
+        new: i64,
+    },
+    StepLimit,
+    Error(VmError),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub step: usize,
+    pub pointer: usize,
+    pub opcode: i64,
+    pub operand: i64,
+    pub registers: [i64; 3],
+    pub output: Option<i64>,
+}
+
+impl TraceEntry {
+    pub fn instruction(&self) -> String {
+        disassemble(&[self.opcode, self.operand])
+    }
+}
+
+pub struct Debugger {
+    computer: Computer,
+    program: Vec<i64>,
+    breakpoints: Vec<usize>,
+    watches: Vec<Register>,
+    step_limit: Option<usize>,
+    steps: usize,
+    output: Vec<i64>,
+    trace: Vec<TraceEntry>,
+}
+
+impl Debugger {
+    pub fn new(input: &str) -> Self {
+        let (computer, program) = parse(input);
+        Self::with_program(computer.a, computer.b, computer.c, program)
+    }
+
+    pub fn with_program(a: i64, b: i64, c: i64, program: Vec<i64>) -> Self {
+        Debugger {
+            computer: Computer::new(a, b, c),
+            program,
+            breakpoints: vec![],
+            watches: vec![],
+            step_limit: None,
+            steps: 0,
+            output: vec![],
+            trace: vec![],
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, pointer: usize) {
+        if !self.breakpoints.contains(&pointer) {
+            self.breakpoints.push(pointer);
+        }
+    }
+
+    pub fn remove_breakpoint(&mut self, pointer: usize) {
+        self.breakpoints.retain(|&p| p != pointer);
+    }
+
+    pub fn watch(&mut self, register: Register) {
+        if !self.watches.contains(&register) {
+            self.watches.push(register);
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.computer.mode = mode;
+    }
+
+    pub fn set_step_limit(&mut self, limit: Option<usize>) {
+        self.step_limit = limit;
+    }
+
+    pub fn register(&self, register: Register) -> i64 {
+        match register {
+            Register::A => self.computer.a,
+            Register::B => self.computer.b,
+            Register::C => self.computer.c,
+        }
+    }
+
+    pub fn pointer(&self) -> usize {
+        self.computer.pointer
+    }
+
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn output(&self) -> &[i64] {
+        &self.output
+    }
+
+    pub fn trace(&self) -> &[TraceEntry] {
+        &self.trace
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.computer.pointer + 1 >= self.program.len()
+    }
+
+    pub fn step(&mut self) -> Result<Option<&TraceEntry>, VmError> {
+        let pointer = self.computer.pointer;
+        let Some(output) = self.computer.step(&self.program)? else {
+            return Ok(None);
+        };
+        self.output.extend(output);
+        self.trace.push(TraceEntry {
+            step: self.steps,
+            pointer,
+            opcode: self.program[pointer],
+            operand: self.program[pointer + 1],
+            registers: [self.computer.a, self.computer.b, self.computer.c],
+            output,
+        });
+        self.steps += 1;
+        Ok(self.trace.last())
+    }
+
+    // Runs until the program halts, hits the step limit, reaches a
+    // breakpoint or changes a watched register. A breakpoint at the current
+    // instruction does not stop the first step, so `run` always progresses.
+    pub fn run(&mut self) -> StopReason {
+        let mut first = true;
+        loop {
+            if self.is_halted() {
+                return StopReason::Halted;
+            }
+            if self.step_limit.is_some_and(|limit| self.steps >= limit) {
+                return StopReason::StepLimit;
+            }
+            if !first && self.breakpoints.contains(&self.computer.pointer) {
+                return StopReason::Breakpoint(self.computer.pointer);
+            }
+            first = false;
+
+            let before = self
+                .watches
+                .iter()
+                .map(|&register| self.register(register))
+                .collect::<Vec<_>>();
+            if let Err(error) = self.step() {
+                return StopReason::Error(error);
+            }
+            for (&register, &old) in self.watches.iter().zip(&before) {
+                let new = self.register(register);
+                if new != old {
+                    return StopReason::Watch { register, old, new };
                 }
-                6 => {
-                    let operand = self.combo(program[self.pointer + 1]);
-                    self.b = self.a / (2i64.pow(operand as u32));
-                    self.pointer += 2;
+            }
+        }
+    }
+
+    pub fn trace_text(&self) -> String {
+        self.trace
+            .iter()
+            .map(|entry| {
+                let mut line = format!(
+                    "{:>6} {:>4}  {:<10} A={} B={} C={}",
+                    entry.step,
+                    entry.pointer,
+                    entry.instruction(),
+                    entry.registers[0],
+                    entry.registers[1],
+                    entry.registers[2]
+                );
+                if let Some(value) = entry.output {
+                    line += &format!(" out={}", value);
                 }
-                7 => {
-                    let operand = self.combo(program[self.pointer + 1]);
-                    self.c = self.a / (2i64.pow(operand as u32));
-                    self.pointer += 2;
+                line
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub fn trace_json(&self) -> String {
+        let entries = self
+            .trace
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{{\"step\":{},\"pointer\":{},\"opcode\":{},\"operand\":{},\"instruction\":\"{}\",\"a\":{},\"b\":{},\"c\":{},\"output\":{}}}",
+                    entry.step,
+                    entry.pointer,
+                    entry.opcode,
+                    entry.operand,
+                    entry.instruction(),
+                    entry.registers[0],
+                    entry.registers[1],
+                    entry.registers[2],
+                    entry.output.map_or("null".to_string(), |value| value.to_string())
+                )
+            })
+            .collect::<Vec<_>>();
+        format!("[{}]", entries.join(","))
+    }
+}
+
+const MNEMONICS: [&str; 8] = ["adv", "bxl", "bst", "jnz", "bxc", "out", "bdv", "cdv"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssembleError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+fn combo_name(operand: i64) -> String {
+    match operand {
+        4 => "A".to_string(),
+        5 => "B".to_string(),
+        6 => "C".to_string(),
+        _ => operand.to_string(),
+    }
+}
+
+fn is_combo(opcode: i64) -> bool {
+    matches!(opcode, 0 | 2 | 5 | 6 | 7)
+}
+
+pub fn disassemble(program: &[i64]) -> String {
+    program
+        .chunks(2)
+        .map(|chunk| match *chunk {
+            [opcode @ 0..=7, operand @ 0..=7] => {
+                let mnemonic = MNEMONICS[opcode as usize];
+                match opcode {
+                    0 | 6 | 7 => format!("{} A, {}", mnemonic, combo_name(operand)),
+                    _ if is_combo(opcode) => format!("{} {}", mnemonic, combo_name(operand)),
+                    _ => format!("{} {}", mnemonic, operand),
                 }
-                _ => panic!("Unexpected"),
             }
+            _ => format!(
+                "data {}",
+                chunk
+                    .iter()
+                    .map(|value| value.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+pub fn assemble(source: &str) -> Result<Vec<i64>, AssembleError> {
+    let mut program = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let error = |message: String| AssembleError {
+            line: index + 1,
+            message,
+        };
+        let line = line.split([';', '#']).next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (mnemonic, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let mnemonic = mnemonic.to_ascii_lowercase();
+        let mut args = rest
+            .split(',')
+            .map(|arg| arg.trim())
+            .filter(|arg| !arg.is_empty())
+            .collect::<Vec<_>>();
+
+        if mnemonic == "data" {
+            if args.is_empty() || args.len() > 2 {
+                return Err(error("data takes one or two values".to_string()));
+            }
+            for arg in args {
+                let value = arg
+                    .parse::<i64>()
+                    .map_err(|_| error(format!("invalid value {:?}", arg)))?;
+                program.push(value);
+            }
+            continue;
+        }
+
+        let Some(opcode) = MNEMONICS.iter().position(|&m| m == mnemonic) else {
+            return Err(error(format!("unknown instruction {:?}", mnemonic)));
+        };
+        let opcode = opcode as i64;
+        if matches!(opcode, 0 | 6 | 7) && args.len() == 2 {
+            if !args[0].eq_ignore_ascii_case("A") {
+                return Err(error(format!("{} always divides A", mnemonic)));
+            }
+            args.remove(0);
+        }
+        let [arg] = args[..] else {
+            return Err(error(format!("{} takes one operand", mnemonic)));
+        };
+        let operand = match arg.to_ascii_uppercase().as_str() {
+            "A" | "B" | "C" if !is_combo(opcode) => {
+                return Err(error(format!("{} takes a literal operand", mnemonic)));
+            }
+            "A" => 4,
+            "B" => 5,
+            "C" => 6,
+            _ => arg
+                .parse::<i64>()
+                .ok()
+                .filter(|operand| (0..8).contains(operand))
+                .ok_or_else(|| error(format!("invalid operand {:?}", arg)))?,
+        };
+        if is_combo(opcode) && (4..7).contains(&operand) && arg.parse::<i64>().is_ok() {
+            return Err(error(format!(
+                "combo operand {} is register {}, write it by name",
+                operand,
+                combo_name(operand)
+            )));
         }
-        output
+        program.push(opcode);
+        program.push(operand);
     }
+    Ok(program)
 }
 
 fn parse(input: &str) -> (Computer, Vec<i64>) {
@@ -112,29 +537,126 @@ pub fn part1(input: &str) -> String {
         .join(",")
 }
 
-pub fn part2(input: &str) -> i64 {
-    let (mut computer, program) = parse(input);
-    let mut results = vec![0];
-    for digit in 0..program.len() {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Analysis {
+    pub shift: u32,
+    pub outputs_per_iteration: usize,
+}
+
+// Recognizes programs that form a single `jnz 0` loop which shifts A right by
+// a constant each iteration, and whose B and C are rewritten before being read
+// in every iteration. Then each iteration's output depends only on the bits of
+// A that are still left, so A can be built from the last output backwards.
+pub fn analyze(program: &[i64]) -> Option<Analysis> {
+    if program.len() < 2
+        || !program.len().is_multiple_of(2)
+        || program[program.len() - 2..] != [3, 0]
+    {
+        return None;
+    }
+    let body = &program[..program.len() - 2];
+    let mut shift = None;
+    let mut outputs = 0;
+    let (mut b_written, mut c_written) = (false, false);
+    for instruction in body.chunks(2) {
+        let (opcode, operand) = (instruction[0], instruction[1]);
+        let reads_b = match opcode {
+            1 | 4 => true,
+            0 | 2 | 5 | 6 | 7 => operand == 5,
+            _ => false,
+        };
+        let reads_c = opcode == 4 || (matches!(opcode, 0 | 2 | 5 | 6 | 7) && operand == 6);
+        if (reads_b && !b_written) || (reads_c && !c_written) {
+            return None;
+        }
+        match opcode {
+            0 if (1..=3).contains(&operand) && shift.is_none() => shift = Some(operand as u32),
+            0 | 3 => return None,
+            2 | 6 => b_written = true,
+            5 => outputs += 1,
+            7 => c_written = true,
+            1 | 4 => (),
+            _ => return None,
+        }
+    }
+    if outputs == 0 {
+        return None;
+    }
+    Some(Analysis {
+        shift: shift?,
+        outputs_per_iteration: outputs,
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SearchResult {
+    Found(i64),
+    NoSolution,
+    NotFoundWithin(i64),
+}
+
+const SEARCH_STEP_LIMIT: usize = 100_000;
+
+fn output_for(program: &[i64], a: i64, b: i64, c: i64) -> Option<Vec<i64>> {
+    run(program, [a, b, c], Mode::Lenient, Some(SEARCH_STEP_LIMIT)).ok()
+}
+
+fn search_digits(
+    program: &[i64],
+    target: &[i64],
+    b: i64,
+    c: i64,
+    analysis: Analysis,
+) -> SearchResult {
+    let per_iteration = analysis.outputs_per_iteration;
+    if target.is_empty() || !target.len().is_multiple_of(per_iteration) {
+        return SearchResult::NoSolution;
+    }
+    let mut results = vec![0i64];
+    for level in 1..=target.len() / per_iteration {
+        let suffix = &target[target.len() - level * per_iteration..];
         let mut next_results = vec![];
         for r in results {
-            for i in 0..8 {
-                computer.a = r * 8 + i;
-                computer.b = 0;
-                computer.c = 0;
-                computer.pointer = 0;
-                let output = computer.execute(&program);
-                if output.len() != digit + 1 {
-                    continue;
-                }
-                if output[output.len() - 1 - digit] == program[program.len() - 1 - digit] {
-                    next_results.push(r * 8 + i);
+            if r >= 1 << (63 - analysis.shift) {
+                continue;
+            }
+            for i in 0..1 << analysis.shift {
+                let a = (r << analysis.shift) | i;
+                if output_for(program, a, b, c).is_some_and(|output| output == suffix) {
+                    next_results.push(a);
                 }
             }
         }
         results = next_results;
     }
-    results[0]
+    results
+        .into_iter()
+        .min()
+        .map_or(SearchResult::NoSolution, SearchResult::Found)
+}
+
+// Smallest A that makes the program print `target`, using the digit-by-digit
+// search when `analyze` recognizes the program and trying every A up to
+// `bound` otherwise.
+pub fn find_a(program: &[i64], target: &[i64], b: i64, c: i64, bound: i64) -> SearchResult {
+    if let Some(analysis) = analyze(program) {
+        return search_digits(program, target, b, c, analysis);
+    }
+    (0..=bound)
+        .find(|&a| output_for(program, a, b, c).is_some_and(|output| output == target))
+        .map_or(SearchResult::NotFoundWithin(bound), SearchResult::Found)
+}
+
+pub fn find_quine(program: &[i64], b: i64, c: i64, bound: i64) -> SearchResult {
+    find_a(program, program, b, c, bound)
+}
+
+pub fn part2(input: &str) -> i64 {
+    let (computer, program) = parse(input);
+    match find_quine(&program, computer.b, computer.c, 1 << 20) {
+        SearchResult::Found(a) => a,
+        _ => panic!("No solution"),
+    }
 }
 
 #[cfg(test)]
@@ -166,4 +688,238 @@ mod tests {
     fn test_day17_part2() {
         assert_eq!(part2(INPUT2), 117440);
     }
+
+    #[test]
+    fn test_day17_disassemble() {
+        let (_, program) = parse(INPUT1);
+        assert_eq!(disassemble(&program), "adv A, 1\nout A\njnz 0");
+        let program = vec![2, 4, 1, 3, 7, 5, 4, 1, 0, 3, 5, 5, 3, 0];
+        assert_eq!(
+            disassemble(&program),
+            "bst A\nbxl 3\ncdv A, B\nbxc 1\nadv A, 3\nout B\njnz 0"
+        );
+        assert_eq!(disassemble(&[5, 7, 9, 1, 3]), "out 7\ndata 9, 1\ndata 3");
+    }
+
+    #[test]
+    fn test_day17_assemble() {
+        let source = "
+            ; reads three bits of A per iteration
+            bst A
+            bxl 3
+            cdv A, B   # C = A >> B
+            bxc 1
+            adv 3
+            out B
+            jnz 0
+        ";
+        assert_eq!(
+            assemble(source),
+            Ok(vec![2, 4, 1, 3, 7, 5, 4, 1, 0, 3, 5, 5, 3, 0])
+        );
+        assert_eq!(assemble("OUT a"), Ok(vec![5, 4]));
+
+        let error = |source| assemble(source).unwrap_err().to_string();
+        assert_eq!(error("out A\nmul 3"), "line 2: unknown instruction \"mul\"");
+        assert_eq!(error("bxl A"), "line 1: bxl takes a literal operand");
+        assert_eq!(error("out 8"), "line 1: invalid operand \"8\"");
+        assert_eq!(
+            error("out 5"),
+            "line 1: combo operand 5 is register B, write it by name"
+        );
+        assert_eq!(error("bdv B, 2"), "line 1: bdv always divides A");
+        assert_eq!(error("jnz"), "line 1: jnz takes one operand");
+    }
+
+    #[test]
+    fn test_day17_round_trip() {
+        for opcode in 0..8 {
+            for operand in 0..8 {
+                let program = vec![opcode, operand];
+                assert_eq!(assemble(&disassemble(&program)), Ok(program));
+            }
+        }
+        let program = vec![0, 3, 5, 4, 3, 0, 11, 2, 6];
+        assert_eq!(assemble(&disassemble(&program)), Ok(program));
+    }
+
+    #[test]
+    fn test_day17_debugger() {
+        let mut debugger = Debugger::new(INPUT1);
+        let entry = debugger.step().unwrap().unwrap().clone();
+        assert_eq!(entry.instruction(), "adv A, 1");
+        assert_eq!(entry.registers, [364, 0, 0]);
+        assert_eq!(debugger.pointer(), 2);
+
+        debugger.add_breakpoint(4);
+        assert_eq!(debugger.run(), StopReason::Breakpoint(4));
+        assert_eq!(debugger.output(), &[4]);
+        assert_eq!(debugger.run(), StopReason::Breakpoint(4));
+        assert_eq!(debugger.output(), &[4, 6]);
+        debugger.remove_breakpoint(4);
+        assert_eq!(debugger.run(), StopReason::Halted);
+        assert_eq!(debugger.output(), &[4, 6, 3, 5, 6, 3, 5, 2, 1, 0]);
+        assert_eq!(debugger.register(Register::A), 0);
+        assert_eq!(debugger.step(), Ok(None));
+
+        let mut debugger = Debugger::new(INPUT1);
+        debugger.watch(Register::A);
+        assert_eq!(
+            debugger.run(),
+            StopReason::Watch {
+                register: Register::A,
+                old: 729,
+                new: 364
+            }
+        );
+    }
+
+    #[test]
+    fn test_day17_step_limit() {
+        let mut debugger = Debugger::with_program(1, 0, 0, vec![1, 1, 3, 0]);
+        debugger.set_step_limit(Some(1000));
+        assert_eq!(debugger.run(), StopReason::StepLimit);
+        assert_eq!(debugger.steps(), 1000);
+        assert_eq!(debugger.trace().len(), 1000);
+        debugger.set_step_limit(Some(1001));
+        assert_eq!(debugger.run(), StopReason::StepLimit);
+        assert_eq!(debugger.steps(), 1001);
+    }
+
+    #[test]
+    fn test_day17_trace() {
+        let mut debugger = Debugger::with_program(10, 0, 0, vec![0, 1, 5, 4, 3, 0]);
+        debugger.set_step_limit(Some(3));
+        debugger.run();
+        assert_eq!(
+            debugger.trace_text(),
+            "     0    0  adv A, 1   A=5 B=0 C=0\n     1    2  out A      A=5 B=0 C=0 out=5\n     2    4  jnz 0      A=5 B=0 C=0"
+        );
+        assert_eq!(
+            debugger.trace_json(),
+            "[{\"step\":0,\"pointer\":0,\"opcode\":0,\"operand\":1,\"instruction\":\"adv A, 1\",\"a\":5,\"b\":0,\"c\":0,\"output\":null},\
+             {\"step\":1,\"pointer\":2,\"opcode\":5,\"operand\":4,\"instruction\":\"out A\",\"a\":5,\"b\":0,\"c\":0,\"output\":5},\
+             {\"step\":2,\"pointer\":4,\"opcode\":3,\"operand\":0,\"instruction\":\"jnz 0\",\"a\":5,\"b\":0,\"c\":0,\"output\":null}]"
+        );
+    }
+
+    #[test]
+    fn test_day17_strict() {
+        let strict = |program: &[i64], a| run(program, [a, 0, 0], Mode::Strict, Some(100));
+        let lenient = |program: &[i64], a| run(program, [a, 0, 0], Mode::Lenient, Some(100));
+
+        assert_eq!(
+            strict(&[5, 7], 0),
+            Err(VmError::ReservedOperand { pointer: 0 })
+        );
+        assert_eq!(lenient(&[5, 7], 0), Ok(vec![7]));
+
+        assert_eq!(
+            strict(&[1, 1, 8, 0], 0),
+            Err(VmError::UnknownOpcode {
+                pointer: 2,
+                opcode: 8
+            })
+        );
+        assert_eq!(lenient(&[1, 1, 8, 0, 5, 5], 0), Ok(vec![1]));
+
+        assert_eq!(
+            strict(&[3, 6], 1),
+            Err(VmError::JumpOutOfRange {
+                pointer: 0,
+                target: 6
+            })
+        );
+        assert_eq!(lenient(&[3, 6], 1), Ok(vec![]));
+        assert_eq!(lenient(&[3, -2], 1), Ok(vec![]));
+
+        assert_eq!(
+            strict(&[2, 4, 1, 7, 0, 5, 5, 4], -1),
+            Err(VmError::NegativeShift {
+                pointer: 4,
+                amount: -8
+            })
+        );
+
+        assert_eq!(lenient(&[0, 4, 5, 4], i64::MAX), Ok(vec![0]));
+        assert_eq!(lenient(&[6, 4, 5, 5], 63), Ok(vec![0]));
+        assert_eq!(strict(&[7, 3, 5, 6], 80), Ok(vec![2]));
+
+        assert_eq!(
+            lenient(&[1, 1, 3, 0], 1),
+            Err(VmError::StepLimit { steps: 100 })
+        );
+        assert_eq!(lenient(&[1, 1, 3, 0], 0), Ok(vec![]));
+        assert_eq!(
+            VmError::StepLimit { steps: 100 }.to_string(),
+            "step limit of 100 reached"
+        );
+
+        let mut debugger = Debugger::with_program(0, 0, 0, vec![5, 4, 5, 7]);
+        debugger.set_mode(Mode::Strict);
+        assert_eq!(
+            debugger.run(),
+            StopReason::Error(VmError::ReservedOperand { pointer: 2 })
+        );
+        assert_eq!(debugger.output(), &[0]);
+    }
+
+    #[test]
+    fn test_day17_analyze() {
+        let (_, program) = parse(INPUT2);
+        assert_eq!(
+            analyze(&program),
+            Some(Analysis {
+                shift: 3,
+                outputs_per_iteration: 1
+            })
+        );
+        let program = assemble("bst A\nbxl 3\ncdv A, B\nbxc 1\nadv 3\nout B\njnz 0").unwrap();
+        assert!(analyze(&program).is_some());
+        assert_eq!(analyze(&[1, 1, 0, 1, 5, 5, 3, 0]), None);
+        assert_eq!(analyze(&[0, 4, 5, 4, 3, 0]), None);
+        assert_eq!(analyze(&[5, 4]), None);
+    }
+
+    #[test]
+    fn test_day17_find_a() {
+        let (_, program) = parse(INPUT2);
+        assert_eq!(find_quine(&program, 0, 0, 0), SearchResult::Found(117440));
+        assert_eq!(
+            find_a(&program, &[1, 2, 0], 0, 0, 0),
+            SearchResult::Found(136)
+        );
+        assert_eq!(find_a(&program, &[1, 2], 0, 0, 0), SearchResult::NoSolution);
+        assert_eq!(find_a(&program, &[0], 0, 0, 0), SearchResult::Found(0));
+        assert_eq!(
+            run(&program, [80, 0, 0], Mode::Strict, None),
+            Ok(vec![2, 1, 0])
+        );
+
+        let program = assemble("adv 1\nout 0\nout 0\njnz 0").unwrap();
+        assert_eq!(
+            find_a(&program, &[0, 0, 0, 0], 0, 0, 0),
+            SearchResult::Found(2)
+        );
+        assert_eq!(
+            find_a(&program, &[0, 0, 0], 0, 0, 0),
+            SearchResult::NoSolution
+        );
+        assert_eq!(find_a(&program, &[1, 0], 0, 0, 0), SearchResult::NoSolution);
+
+        let program = [1, 1, 0, 1, 5, 5, 3, 0];
+        assert_eq!(
+            find_a(&program, &[1, 0, 1], 0, 0, 1000),
+            SearchResult::Found(4)
+        );
+        assert_eq!(
+            find_a(&program, &[0], 0, 0, 1000),
+            SearchResult::NotFoundWithin(1000)
+        );
+        assert_eq!(find_a(&[5, 4], &[5], 0, 0, 100), SearchResult::Found(5));
+        assert_eq!(
+            find_a(&[1, 1, 3, 0], &[7], 0, 0, 10),
+            SearchResult::NotFoundWithin(10)
+        );
+    }
 }
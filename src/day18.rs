@@ -7,10 +7,9 @@ struct Position {
 }
 
 impl Position {
-    fn heuristic(&self, d: i64, width: usize, height: usize) -> i64 {
-        -(d + (width - self.x + height - self.y) as i64)
+    fn heuristic(&self, d: i64, goal: Position) -> i64 {
+        -(d + (self.x.abs_diff(goal.x) + self.y.abs_diff(goal.y)) as i64)
     }
-
     fn neighbors(&self, width: usize, height: usize) -> Vec<Position> {
         let mut result = Vec::with_capacity(4);
         if self.x > 0 {
@@ -41,7 +40,7 @@ impl Position {
     }
 }
 
-fn parse(input: &str) -> Vec<(usize, usize)> {
+pub fn parse(input: &str) -> Vec<(usize, usize)> {
     input
         .trim()
         .lines()
@@ -56,58 +55,245 @@ fn parse(input: &str) -> Vec<(usize, usize)> {
         .collect()
 }
 
-fn solve(obstacles: &[(usize, usize)], width: usize, height: usize) -> i64 {
-    let mut field = vec![vec![false; width]; height];
-    for o in obstacles {
-        field[o.1][o.0] = true;
+struct UnionFind {
+    parent: Vec<usize>,
+}
+
+impl UnionFind {
+    fn new(size: usize) -> Self {
+        UnionFind {
+            parent: (0..size).collect(),
+        }
     }
-    let mut visited = vec![vec![i64::MAX; width]; height];
-    let mut heap: BinaryHeap<(i64, i64, Position)> = BinaryHeap::with_capacity(1024);
-    let start = Position { x: 0, y: 0 };
-    heap.push((start.heuristic(0, width, height), 0, start));
-    let mut result = i64::MAX;
-    while let Some((_, d, position)) = heap.pop() {
-        if d >= result {
-            continue;
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
         }
+        x
+    }
 
-        if position.x == width - 1 && position.y == height - 1 {
-            result = result.min(d);
-            continue;
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        self.parent[a.max(b)] = a.min(b);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MemorySpace {
+    pub obstacles: Vec<(usize, usize)>,
+    pub width: usize,
+    pub height: usize,
+    pub bytes: usize,
+    pub start: (usize, usize),
+    pub goal: (usize, usize),
+}
+
+impl MemorySpace {
+    pub fn new(input: &str, width: usize, height: usize) -> Self {
+        let obstacles = parse(input);
+        MemorySpace {
+            bytes: obstacles.len(),
+            obstacles,
+            width,
+            height,
+            start: (0, 0),
+            goal: (width.saturating_sub(1), height.saturating_sub(1)),
         }
+    }
 
-        if visited[position.y][position.x] <= d {
-            continue;
+    fn contains(&self, (x, y): (usize, usize)) -> bool {
+        x < self.width && y < self.height
+    }
+
+    // Start and goal are public fields, so every search checks them first.
+    fn endpoints_inside(&self) -> bool {
+        self.contains(self.start) && self.contains(self.goal)
+    }
+
+    // Bytes that land outside the space are ignored.
+    fn field(&self, bytes: usize) -> Vec<Vec<bool>> {
+        let mut field = vec![vec![false; self.width]; self.height];
+        for &(x, y) in self.obstacles.iter().take(bytes) {
+            if self.contains((x, y)) {
+                field[y][x] = true;
+            }
         }
+        field
+    }
 
-        visited[position.y][position.x] = d;
+    fn find_path(&self, field: &[Vec<bool>]) -> Option<Vec<Position>> {
+        if !self.endpoints_inside() {
+            return None;
+        }
+        let (width, height) = (self.width, self.height);
+        let start = Position {
+            x: self.start.0,
+            y: self.start.1,
+        };
+        let goal = Position {
+            x: self.goal.0,
+            y: self.goal.1,
+        };
+        if field[start.y][start.x] || field[goal.y][goal.x] {
+            return None;
+        }
+
+        let mut best = vec![vec![i64::MAX; width]; height];
+        let mut previous = vec![vec![None; width]; height];
+        let mut heap: BinaryHeap<(i64, i64, Position)> = BinaryHeap::with_capacity(1024);
+        best[start.y][start.x] = 0;
+        heap.push((start.heuristic(0, goal), 0, start));
+        while let Some((_, d, position)) = heap.pop() {
+            if position == goal {
+                let mut path = vec![goal];
+                while let Some(last) = previous[path[path.len() - 1].y][path[path.len() - 1].x] {
+                    path.push(last);
+                }
+                path.reverse();
+                return Some(path);
+            }
+
+            if best[position.y][position.x] < d {
+                continue;
+            }
 
-        for neighbor in position.neighbors(width, height) {
-            if visited[neighbor.y][neighbor.x] > d + 1 && !field[neighbor.y][neighbor.x] {
-                heap.push((neighbor.heuristic(d + 1, width, height), d + 1, neighbor));
+            for neighbor in position.neighbors(width, height) {
+                if best[neighbor.y][neighbor.x] > d + 1 && !field[neighbor.y][neighbor.x] {
+                    best[neighbor.y][neighbor.x] = d + 1;
+                    previous[neighbor.y][neighbor.x] = Some(position);
+                    heap.push((neighbor.heuristic(d + 1, goal), d + 1, neighbor));
+                }
             }
         }
+        None
     }
-    result
-}
 
-fn find_block(input: &str, width: usize, height: usize) -> String {
-    let obstacles = parse(input);
-    let (mut i, mut j) = (0, obstacles.len() - 1);
-    while i < j - 1 {
-        let m = (i + j) / 2;
-        if solve(&obstacles[..m], width, height) < i64::MAX {
-            i = m;
-        } else {
-            j = m;
+    pub fn shortest_path(&self) -> Option<Vec<(usize, usize)>> {
+        let path = self.find_path(&self.field(self.bytes))?;
+        Some(path.into_iter().map(|p| (p.x, p.y)).collect())
+    }
+
+    pub fn distance(&self) -> Option<usize> {
+        self.shortest_path().map(|path| path.len() - 1)
+    }
+
+    pub fn render(&self, path: &[(usize, usize)]) -> String {
+        let mut grid = self
+            .field(self.bytes)
+            .iter()
+            .map(|line| {
+                line.iter()
+                    .map(|&byte| if byte { '#' } else { '.' })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        for &(x, y) in path.iter().filter(|&&cell| self.contains(cell)) {
+            grid[y][x] = 'O';
         }
+        grid.iter()
+            .map(|line| line.iter().collect::<String>())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // Index of the first byte after which the goal can no longer be reached,
+    // looking at every byte regardless of `bytes`. All bytes are dropped up
+    // front and then lifted again in reverse order, merging the freed cell with
+    // its free neighbors, until start and goal are joined.
+    pub fn blocking_byte(&self) -> Option<usize> {
+        if !self.endpoints_inside() {
+            return None;
+        }
+        let (width, height) = (self.width, self.height);
+        let cell = |x: usize, y: usize| y * width + x;
+        let mut fallen = vec![usize::MAX; width * height];
+        for (i, &(x, y)) in self.obstacles.iter().enumerate().rev() {
+            if self.contains((x, y)) {
+                fallen[cell(x, y)] = i;
+            }
+        }
+
+        let mut sets = UnionFind::new(width * height);
+        let open = |sets: &mut UnionFind, fallen: &[usize], position: Position, bytes: usize| {
+            for neighbor in position.neighbors(width, height) {
+                if fallen[cell(neighbor.x, neighbor.y)] >= bytes {
+                    sets.union(cell(position.x, position.y), cell(neighbor.x, neighbor.y));
+                }
+            }
+        };
+        for y in 0..height {
+            for x in 0..width {
+                if fallen[cell(x, y)] == usize::MAX {
+                    open(&mut sets, &fallen, Position { x, y }, self.obstacles.len());
+                }
+            }
+        }
+
+        let (start, goal) = (
+            cell(self.start.0, self.start.1),
+            cell(self.goal.0, self.goal.1),
+        );
+        let connected = |sets: &mut UnionFind, bytes: usize| {
+            fallen[start] >= bytes && fallen[goal] >= bytes && sets.find(start) == sets.find(goal)
+        };
+        if connected(&mut sets, self.obstacles.len()) {
+            return None;
+        }
+        for (i, &(x, y)) in self.obstacles.iter().enumerate().rev() {
+            if !self.contains((x, y)) || fallen[cell(x, y)] != i {
+                continue;
+            }
+            open(&mut sets, &fallen, Position { x, y }, i);
+            if connected(&mut sets, i) {
+                return Some(i);
+            }
+        }
+        None
+    }
+
+    // Shortest distance to the goal after each byte has fallen, or None once
+    // the goal is cut off. The path is only searched again when a byte lands
+    // on it.
+    pub fn degradation(&self) -> Vec<Option<usize>> {
+        let mut field = self.field(0);
+        let mut path = self.find_path(&field);
+        let mut on_path = vec![vec![false; self.width]; self.height];
+        for position in path.iter().flatten() {
+            on_path[position.y][position.x] = true;
+        }
+        self.obstacles
+            .iter()
+            .map(|&(x, y)| {
+                if self.contains((x, y)) {
+                    field[y][x] = true;
+                    if path.is_some() && on_path[y][x] {
+                        path = self.find_path(&field);
+                        on_path = vec![vec![false; self.width]; self.height];
+                        for position in path.iter().flatten() {
+                            on_path[position.y][position.x] = true;
+                        }
+                    }
+                }
+                path.as_ref().map(|path| path.len() - 1)
+            })
+            .collect()
     }
-    format!("{},{}", obstacles[j - 1].0, obstacles[j - 1].1).to_string()
+}
+
+fn find_block(input: &str, width: usize, height: usize) -> String {
+    let space = MemorySpace::new(input, width, height);
+    let i = space.blocking_byte().expect("Exit is never blocked");
+    format!("{},{}", space.obstacles[i].0, space.obstacles[i].1)
 }
 
 pub fn part1(input: &str) -> i64 {
-    let obstacles = parse(input);
-    solve(&obstacles[..1024], 71, 71)
+    let space = MemorySpace {
+        bytes: 1024,
+        ..MemorySpace::new(input, 71, 71)
+    };
+    space.distance().unwrap() as i64
 }
 
 pub fn part2(input: &str) -> String {
@@ -146,14 +332,135 @@ mod tests {
         2,0
         ";
 
+    fn space(bytes: usize) -> MemorySpace {
+        MemorySpace {
+            bytes,
+            ..MemorySpace::new(INPUT, 7, 7)
+        }
+    }
+
     #[test]
     fn test_day18_part1() {
-        let obstacles = parse(INPUT);
-        assert_eq!(solve(&obstacles[..12], 7, 7), 22);
+        assert_eq!(space(12).distance(), Some(22));
     }
 
     #[test]
     fn test_day18_part2() {
         assert_eq!(find_block(INPUT, 7, 7), "6,1".to_string());
     }
+
+    #[test]
+    fn test_day18_shortest_path() {
+        let space = space(12);
+        let path = space.shortest_path().unwrap();
+        assert_eq!(path.len(), 23);
+        assert_eq!((path[0], path[22]), ((0, 0), (6, 6)));
+        for pair in path.windows(2) {
+            assert_eq!(
+                pair[0].0.abs_diff(pair[1].0) + pair[0].1.abs_diff(pair[1].1),
+                1
+            );
+        }
+        assert!(path
+            .iter()
+            .all(|cell| !space.obstacles[..12].contains(cell)));
+
+        let space = MemorySpace {
+            obstacles: vec![(1, 0), (1, 1)],
+            ..MemorySpace::new("", 3, 3)
+        };
+        assert_eq!(space.bytes, 0);
+        let space = MemorySpace { bytes: 2, ..space };
+        let path = space.shortest_path().unwrap();
+        assert_eq!(space.render(&path), "O#.\nO#.\nOOO");
+        assert_eq!(space.render(&[]), ".#.\n.#.\n...");
+    }
+
+    #[test]
+    fn test_day18_memory_space() {
+        let reversed = MemorySpace {
+            start: (6, 6),
+            goal: (0, 0),
+            ..space(12)
+        };
+        assert_eq!(reversed.distance(), Some(22));
+        let inner = MemorySpace {
+            start: (6, 0),
+            goal: (3, 4),
+            ..space(12)
+        };
+        assert_eq!(inner.distance(), Some(7));
+        let small = MemorySpace {
+            width: 4,
+            height: 4,
+            goal: (3, 3),
+            ..space(12)
+        };
+        assert_eq!(small.distance(), None);
+        assert_eq!(small.render(&[]), "...#\n..#.\n....\n...#");
+        let blocked = MemorySpace {
+            start: (5, 4),
+            ..space(12)
+        };
+        assert_eq!(blocked.distance(), None);
+        let same = MemorySpace {
+            goal: (0, 0),
+            ..space(12)
+        };
+        assert_eq!(same.shortest_path(), Some(vec![(0, 0)]));
+    }
+
+    #[test]
+    fn test_day18_blocking_byte() {
+        let space = |obstacles: Vec<(usize, usize)>| MemorySpace {
+            obstacles,
+            ..MemorySpace::new("", 3, 3)
+        };
+        assert_eq!(MemorySpace::new(INPUT, 7, 7).blocking_byte(), Some(20));
+        let first = MemorySpace {
+            obstacles: parse(INPUT)[..20].to_vec(),
+            ..MemorySpace::new(INPUT, 7, 7)
+        };
+        assert_eq!(first.blocking_byte(), None);
+        assert_eq!(space(vec![(1, 1), (0, 0)]).blocking_byte(), Some(1));
+        assert_eq!(space(vec![(1, 1), (2, 2), (2, 2)]).blocking_byte(), Some(1));
+        assert_eq!(
+            space(vec![(1, 1), (5, 5), (0, 2), (2, 0)]).blocking_byte(),
+            Some(3)
+        );
+    }
+
+    #[test]
+    fn test_day18_degradation() {
+        let curve = space(0).degradation();
+        assert_eq!(curve[11], Some(22));
+        for (i, distance) in curve.iter().enumerate() {
+            assert_eq!(*distance, space(i + 1).distance());
+        }
+        assert_eq!(curve.iter().position(|d| d.is_none()), Some(20));
+    }
+
+    #[test]
+    fn test_day18_outside() {
+        let empty = MemorySpace::new(INPUT, 0, 5);
+        assert_eq!(empty.distance(), None);
+        assert_eq!(empty.blocking_byte(), None);
+        assert!(empty.degradation().iter().all(|d| d.is_none()));
+        assert_eq!(empty.render(&[]), "\n\n\n\n");
+
+        for (start, goal) in [((7, 0), (6, 6)), ((0, 0), (0, 9)), ((9, 9), (9, 9))] {
+            let space = MemorySpace {
+                start,
+                goal,
+                ..space(12)
+            };
+            assert_eq!(space.shortest_path(), None);
+            assert_eq!(space.blocking_byte(), None);
+            assert!(space.degradation().iter().all(|d| d.is_none()));
+        }
+        assert_eq!(
+            space(0).render(&[(0, 0), (8, 1)]).lines().next(),
+            Some("O......")
+        );
+    }
 }
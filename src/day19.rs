@@ -1,36 +1,200 @@
-use ahash::AHashMap;
+const NONE: u32 = u32::MAX;
 
-fn check(pattern: &str, parts: &[&str]) -> bool {
-    if pattern.is_empty() {
-        return true;
+// Trie over the towels, with the children of each node stored in one flat
+// table indexed by the node and a dense id for every color in use.
+pub struct Towels {
+    alphabet: [u8; 256],
+    symbols: usize,
+    children: Vec<u32>,
+    terminal: Vec<bool>,
+}
+
+impl Towels {
+    pub fn new(towels: &[&str]) -> Self {
+        let mut alphabet = [u8::MAX; 256];
+        let mut symbols = 0;
+        for &byte in towels.iter().flat_map(|towel| towel.as_bytes()) {
+            if alphabet[byte as usize] == u8::MAX {
+                alphabet[byte as usize] = symbols as u8;
+                symbols += 1;
+            }
+        }
+        let mut trie = Towels {
+            alphabet,
+            symbols,
+            children: vec![NONE; symbols],
+            terminal: vec![false],
+        };
+        for towel in towels {
+            let mut node = 0;
+            for &byte in towel.as_bytes() {
+                let index = node * symbols + alphabet[byte as usize] as usize;
+                if trie.children[index] == NONE {
+                    trie.children[index] = trie.terminal.len() as u32;
+                    trie.terminal.push(false);
+                    trie.children.extend(std::iter::repeat_n(NONE, symbols));
+                }
+                node = trie.children[index] as usize;
+            }
+            trie.terminal[node] = true;
+        }
+        trie
     }
-    for part in parts {
-        if part.len() <= pattern.len()
-            && *part == &pattern[..part.len()]
-            && check(&pattern[part.len()..], parts)
-        {
-            return true;
+
+    // Calls `f` with the end of every towel that matches the design at `start`.
+    fn matches(&self, design: &[u8], start: usize, mut f: impl FnMut(usize)) {
+        let mut node = 0;
+        for (end, &byte) in design.iter().enumerate().skip(start) {
+            let symbol = self.alphabet[byte as usize];
+            if symbol == u8::MAX {
+                return;
+            }
+            node = self.children[node * self.symbols + symbol as usize] as usize;
+            if node == NONE as usize {
+                return;
+            }
+            if self.terminal[node] {
+                f(end + 1);
+            }
         }
     }
-    false
+
+    pub fn possible(&self, design: &str) -> bool {
+        let design = design.as_bytes();
+        let mut reachable = vec![false; design.len() + 1];
+        reachable[0] = true;
+        for start in 0..design.len() {
+            if reachable[start] {
+                self.matches(design, start, |end| reachable[end] = true);
+            }
+        }
+        reachable[design.len()]
+    }
+
+    // Number of arrangements, or None if it does not fit in a u128.
+    pub fn count(&self, design: &str) -> Option<u128> {
+        let design = design.as_bytes();
+        let mut ways = vec![Some(0u128); design.len() + 1];
+        ways[design.len()] = Some(1);
+        for start in (0..design.len()).rev() {
+            let mut total = Some(0u128);
+            self.matches(design, start, |end| {
+                total = match (total, ways[end]) {
+                    (_, Some(0)) => total,
+                    (Some(total), Some(ways)) => total.checked_add(ways),
+                    _ => None,
+                }
+            });
+            ways[start] = total;
+        }
+        ways[0]
+    }
+
+    // For every position, the ends of the towels starting there that can be
+    // completed to an arrangement of the rest of the design.
+    fn options(&self, design: &str) -> Vec<Vec<usize>> {
+        let bytes = design.as_bytes();
+        let mut options = vec![vec![]; bytes.len() + 1];
+        let mut solvable = vec![false; bytes.len() + 1];
+        solvable[bytes.len()] = true;
+        for start in (0..bytes.len()).rev() {
+            self.matches(bytes, start, |end| {
+                if solvable[end] {
+                    options[start].push(end);
+                }
+            });
+            solvable[start] = !options[start].is_empty();
+        }
+        options
+    }
+
+    // All arrangements in lexicographic order of their towels.
+    pub fn arrangements<'a>(&self, design: &'a str) -> Arrangements<'a> {
+        let options = self.options(design);
+        let done = !design.is_empty() && options[0].is_empty();
+        Arrangements {
+            design,
+            options,
+            stack: vec![],
+            started: false,
+            done,
+        }
+    }
+
+    pub fn first_arrangement<'a>(&self, design: &'a str) -> Option<Vec<&'a str>> {
+        self.arrangements(design).next()
+    }
+
+    pub fn fewest_towels<'a>(&self, design: &'a str) -> Option<Vec<&'a str>> {
+        let options = self.options(design);
+        if !design.is_empty() && options[0].is_empty() {
+            return None;
+        }
+        let mut fewest = vec![0usize; design.len() + 1];
+        let mut best = vec![0usize; design.len() + 1];
+        for start in (0..design.len()).rev() {
+            if let Some(&end) = options[start].iter().min_by_key(|&&end| fewest[end]) {
+                fewest[start] = fewest[end] + 1;
+                best[start] = end;
+            }
+        }
+        let mut result = vec![];
+        let mut start = 0;
+        while start < design.len() {
+            result.push(&design[start..best[start]]);
+            start = best[start];
+        }
+        Some(result)
+    }
+}
+
+pub struct Arrangements<'a> {
+    design: &'a str,
+    options: Vec<Vec<usize>>,
+    stack: Vec<(usize, usize)>,
+    started: bool,
+    done: bool,
 }
 
-fn count(pattern: &str, parts: &[&str], cache: &mut AHashMap<String, i64>) -> i64 {
-    if pattern.is_empty() {
-        return 1;
+impl<'a> Arrangements<'a> {
+    fn descend(&mut self, mut start: usize) {
+        while start < self.design.len() {
+            self.stack.push((start, 0));
+            start = self.options[start][0];
+        }
     }
-    parts
-        .iter()
-        .filter(|&part| part.len() <= pattern.len() && *part == &pattern[..part.len()])
-        .map(|part| {
-            if let Some(&result) = cache.get(&pattern[part.len()..]) {
-                return result;
+}
+
+impl<'a> Iterator for Arrangements<'a> {
+    type Item = Vec<&'a str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if !self.started {
+            self.started = true;
+            self.descend(0);
+        } else {
+            loop {
+                let Some((start, choice)) = self.stack.pop() else {
+                    self.done = true;
+                    return None;
+                };
+                if choice + 1 < self.options[start].len() {
+                    self.stack.push((start, choice + 1));
+                    self.descend(self.options[start][choice + 1]);
+                    break;
+                }
             }
-            let c = count(&pattern[part.len()..], parts, cache);
-            cache.insert(pattern[part.len()..].to_string(), c);
-            c
-        })
-        .sum()
+        }
+        Some(
+            self.stack
+                .iter()
+                .map(|&(start, choice)| &self.design[start..self.options[start][choice]])
+                .collect(),
+        )
+    }
 }
 
 fn parse(input: &str) -> (Vec<&str>, Vec<&str>) {
@@ -57,18 +221,19 @@ fn parse(input: &str) -> (Vec<&str>, Vec<&str>) {
 
 pub fn part1(input: &str) -> i64 {
     let (parts, patterns) = parse(input);
+    let towels = Towels::new(&parts);
     patterns
         .into_iter()
-        .filter(|&pattern| check(pattern, &parts))
+        .filter(|&pattern| towels.possible(pattern))
         .count() as i64
 }
 
 pub fn part2(input: &str) -> i64 {
     let (parts, patterns) = parse(input);
-    let mut cache: AHashMap<String, i64> = AHashMap::new();
+    let towels = Towels::new(&parts);
     patterns
         .iter()
-        .map(|&pattern| count(pattern, &parts, &mut cache))
+        .map(|&pattern| towels.count(pattern).unwrap() as i64)
         .sum::<i64>()
 }
 
@@ -98,4 +263,87 @@ mod tests {
     fn test_day19_part2() {
         assert_eq!(part2(INPUT), 16);
     }
+
+    #[test]
+    fn test_day19_towels() {
+        let (parts, _) = parse(INPUT);
+        let towels = Towels::new(&parts);
+        assert_eq!(towels.count("gbbr"), Some(4));
+        assert_eq!(towels.count("rrbgbr"), Some(6));
+        assert_eq!(towels.count("ubwu"), Some(0));
+        assert!(!towels.possible("bbrgwb"));
+        assert!(towels.possible(""));
+        assert_eq!(towels.count(""), Some(1));
+        assert!(!towels.possible("xyz"));
+        assert!(!Towels::new(&[]).possible("r"));
+    }
+
+    #[test]
+    fn test_day19_many_towels() {
+        let mut level = vec![String::new()];
+        let mut parts = vec![];
+        for _ in 0..6 {
+            level = level
+                .iter()
+                .flat_map(|part| ["w", "u", "b", "r"].map(|color| part.clone() + color))
+                .collect();
+            parts.extend(level.iter().cloned());
+        }
+        assert_eq!(parts.len(), 5460);
+        let towels = Towels::new(&parts.iter().map(|part| part.as_str()).collect::<Vec<_>>());
+
+        // Every string is made of towels of length 1 to 6 in any order.
+        let mut compositions = vec![1u64; 1];
+        for n in 1..=60 {
+            compositions.push(compositions[n.max(6) - 6..n].iter().sum());
+        }
+        let design = "wubr".repeat(15);
+        assert!(towels.possible(&design));
+        assert_eq!(towels.count(&design), Some(compositions[60] as u128));
+        assert!(!towels.possible(&(design + "g")));
+    }
+
+    #[test]
+    fn test_day19_arrangements() {
+        let (parts, patterns) = parse(INPUT);
+        let towels = Towels::new(&parts);
+        for pattern in patterns {
+            let all = towels.arrangements(pattern).collect::<Vec<_>>();
+            assert_eq!(all.len() as u128, towels.count(pattern).unwrap());
+            assert!(all.iter().all(|towels| towels.concat() == pattern));
+            assert!(all.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(towels.first_arrangement(pattern), all.first().cloned());
+            let fewest = towels.fewest_towels(pattern);
+            assert_eq!(
+                fewest.as_ref().map(|towels| towels.len()),
+                all.iter().map(|towels| towels.len()).min()
+            );
+        }
+        assert_eq!(
+            towels.first_arrangement("rrbgbr"),
+            Some(vec!["r", "r", "b", "g", "b", "r"])
+        );
+        assert_eq!(
+            towels.fewest_towels("rrbgbr"),
+            Some(vec!["r", "rb", "g", "br"])
+        );
+        assert_eq!(towels.fewest_towels("ubwu"), None);
+        assert_eq!(
+            towels.arrangements("").collect::<Vec<_>>(),
+            vec![Vec::<&str>::new()]
+        );
+    }
+
+    #[test]
+    fn test_day19_overflow() {
+        let towels = Towels::new(&["w", "ww"]);
+        assert_eq!(towels.count(&"w".repeat(100)), Some(573147844013817084101));
+        let design = "w".repeat(200);
+        assert_eq!(towels.count(&design), None);
+        assert!(towels.possible(&design));
+        assert_eq!(towels.fewest_towels(&design).unwrap().len(), 100);
+        let mut arrangements = towels.arrangements(&design);
+        assert_eq!(arrangements.next().unwrap().len(), 200);
+        assert_eq!(arrangements.next().unwrap()[198], "ww");
+    }
 }
@@ -1,5 +1,5 @@
 use rayon::prelude::*;
-use std::collections::BinaryHeap;
+use std::collections::{BTreeMap, VecDeque};
 
 const DIRS: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
 
@@ -50,113 +50,124 @@ fn parse(input: &str) -> (Vec<Vec<u8>>, Position, Position) {
     (field, start, end)
 }
 
-fn heuristic(position: &Position, end: &Position) -> i64 {
-    (end.x.abs_diff(position.x)
-        + end.y.abs_diff(position.y)
-        + if end.x != position.x && end.y != position.y {
-            1000
-        } else {
-            0
-        }) as i64
-}
-
-fn find_record(field: &[Vec<u8>], start: Position, end: Position, visited: &mut [Vec<i64>]) -> i64 {
-    let mut heap: BinaryHeap<(i64, i64, Position)> = BinaryHeap::with_capacity(1024);
-    let mut result = i64::MAX;
-    visited[start.y][start.x] = 0;
-    heap.push((heuristic(&start, &end), 0, start));
-    while let Some((_, current_points, position)) = heap.pop() {
-        if current_points >= result {
-            continue;
-        }
+// Breadth-first distance of every cell from `from`, or -1 for cells that
+// cannot be reached.
+fn distances(field: &[Vec<u8>], from: Position) -> Vec<Vec<i64>> {
+    let mut result = vec![vec![-1; field[0].len()]; field.len()];
+    result[from.y][from.x] = 0;
+    let mut queue = VecDeque::from([from]);
+    while let Some(position) = queue.pop_front() {
         for next_position in position.step(field) {
-            let new_points = current_points + 1;
-            if new_points >= result {
-                continue;
-            }
-            if next_position.x == end.x && next_position.y == end.y {
-                result = result.min(new_points);
-            }
-            let prev_points = visited[next_position.y][next_position.x];
-            if prev_points > new_points {
-                visited[next_position.y][next_position.x] = new_points;
-                heap.push((
-                    -heuristic(&next_position, &end) - new_points,
-                    new_points,
-                    next_position,
-                ));
+            if result[next_position.y][next_position.x] == -1 {
+                result[next_position.y][next_position.x] = result[position.y][position.x] + 1;
+                queue.push_back(next_position);
             }
         }
     }
     result
 }
 
-fn find_path(
-    field: &[Vec<u8>],
-    end: Position,
-    visited: &[Vec<i64>],
-    record: i64,
-) -> (Vec<Vec<i64>>, Vec<Position>) {
-    let mut point = (Position { x: end.x, y: end.y }, record);
-    let mut result = vec![vec![-1; field[0].len()]; field.len()];
-    result[end.y][end.x] = record;
-    let mut path = vec![Position { x: end.x, y: end.y }];
-    loop {
-        let (position, value) = point;
-        for prev_position in position.step(field) {
-            if visited[prev_position.y][prev_position.x] == value - 1 {
-                result[prev_position.y][prev_position.x] = value - 1;
-                path.push(prev_position);
-                if value > 0 {
-                    point = (prev_position, value - 1);
-                }
-            }
-        }
-        if value == 0 {
-            break;
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rules {
+    pub max_length: usize,
+    pub min_saving: i64,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            max_length: 2,
+            min_saving: 1,
         }
     }
-    (result, path)
 }
 
-fn solve(input: &str, cheats: i64, difference: i64) -> i64 {
-    let (field, start, end) = parse(input);
-    let mut visited = vec![vec![i64::MAX; field[0].len()]; field.len()];
-    let record = find_record(&field, start, end, &mut visited);
-    let (new_field, path) = find_path(&field, end, &visited, record);
-    path.par_iter()
-        .rev()
-        .map(|position| {
-            (-cheats..=cheats)
-                .map(|y_diff| {
-                    (-cheats + y_diff.abs()..=cheats - y_diff.abs()).fold(0, |acc, x_diff| {
-                        let new_x = position.x as i64 + x_diff;
-                        let new_y = position.y as i64 + y_diff;
-                        if new_x > 0
-                            && new_x < field[0].len() as i64 - 1
-                            && new_y > 0
-                            && new_y < field.len() as i64 - 1
-                        {
-                            let new_x = new_x as usize;
-                            let new_y = new_y as usize;
-                            if new_field[new_y][new_x]
-                                >= new_field[position.y][position.x]
-                                    + x_diff.abs()
-                                    + y_diff.abs()
-                                    + difference
-                            {
-                                acc + 1
-                            } else {
-                                acc
-                            }
-                        } else {
-                            acc
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Cheat {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub length: usize,
+    pub saved: i64,
+}
+
+pub struct Track {
+    field: Vec<Vec<u8>>,
+    record: i64,
+    dist_start: Vec<Vec<i64>>,
+    dist_end: Vec<Vec<i64>>,
+}
+
+impl Track {
+    pub fn new(input: &str) -> Self {
+        let (field, start, end) = parse(input);
+        let dist_start = distances(&field, start);
+        let dist_end = distances(&field, end);
+        Track {
+            record: dist_start[end.y][end.x],
+            field,
+            dist_start,
+            dist_end,
+        }
+    }
+
+    pub fn record(&self) -> Option<i64> {
+        (self.record >= 0).then_some(self.record)
+    }
+
+    // A cheat from `a` to `b` finishes in dist_start[a] + |a - b| + dist_end[b],
+    // which covers every reachable cell, not only those on a fastest route.
+    pub fn cheats(&self, rules: &Rules) -> Vec<Cheat> {
+        if self.record < 0 {
+            return vec![];
+        }
+        let (width, height) = (self.field[0].len() as i64, self.field.len() as i64);
+        let max_length = rules.max_length as i64;
+        let mut cheats = (0..self.field.len())
+            .into_par_iter()
+            .flat_map_iter(|y| (0..self.field[y].len()).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.dist_start[y][x] >= 0)
+            .flat_map_iter(|(x, y)| {
+                let from = self.dist_start[y][x];
+                (-max_length..=max_length).flat_map(move |y_diff| {
+                    let x_range = max_length - y_diff.abs();
+                    (-x_range..=x_range).filter_map(move |x_diff| {
+                        let (new_x, new_y) = (x as i64 + x_diff, y as i64 + y_diff);
+                        if new_x < 0 || new_x >= width || new_y < 0 || new_y >= height {
+                            return None;
                         }
+                        let (new_x, new_y) = (new_x as usize, new_y as usize);
+                        let to = self.dist_end[new_y][new_x];
+                        let length = x_diff.abs() + y_diff.abs();
+                        let saved = self.record - (from + length + to);
+                        (to >= 0 && length > 0 && saved >= rules.min_saving).then_some(Cheat {
+                            start: (x, y),
+                            end: (new_x, new_y),
+                            length: length as usize,
+                            saved,
+                        })
                     })
                 })
-                .sum::<i64>()
-        })
-        .sum()
+            })
+            .collect::<Vec<_>>();
+        cheats.sort_unstable();
+        cheats
+    }
+
+    pub fn histogram(&self, rules: &Rules) -> BTreeMap<i64, usize> {
+        let mut histogram = BTreeMap::new();
+        for cheat in self.cheats(rules) {
+            *histogram.entry(cheat.saved).or_insert(0) += 1;
+        }
+        histogram
+    }
+}
+
+fn solve(input: &str, cheats: usize, difference: i64) -> i64 {
+    let rules = Rules {
+        max_length: cheats,
+        min_saving: difference,
+    };
+    Track::new(input).cheats(&rules).len() as i64
 }
 
 pub fn part1(input: &str) -> i64 {
@@ -198,4 +209,103 @@ mod tests {
     fn test_day20_part2() {
         assert_eq!(solve(INPUT, 20, 50), 285);
     }
+
+    #[test]
+    fn test_day20_histogram() {
+        let track = Track::new(INPUT);
+        assert_eq!(track.record(), Some(84));
+        assert_eq!(
+            track.histogram(&Rules::default()),
+            BTreeMap::from([
+                (2, 14),
+                (4, 14),
+                (6, 2),
+                (8, 4),
+                (10, 2),
+                (12, 3),
+                (20, 1),
+                (36, 1),
+                (38, 1),
+                (40, 1),
+                (64, 1)
+            ])
+        );
+        let rules = Rules {
+            max_length: 20,
+            min_saving: 72,
+        };
+        assert_eq!(
+            track.histogram(&rules),
+            BTreeMap::from([(72, 22), (74, 4), (76, 3)])
+        );
+        assert_eq!(
+            track.cheats(&Rules {
+                max_length: 2,
+                min_saving: 64
+            }),
+            vec![Cheat {
+                start: (7, 7),
+                end: (5, 7),
+                length: 2,
+                saved: 64
+            }]
+        );
+    }
+
+    #[test]
+    fn test_day20_branches() {
+        let track = Track::new(
+            "
+            #######
+            #S...E#
+            #.###.#
+            #.....#
+            #######
+            ",
+        );
+        assert_eq!(track.record(), Some(4));
+        assert!(track.cheats(&Rules::default()).is_empty());
+
+        let track = Track::new(
+            "
+            #########
+            #S.#...E#
+            #..#.#..#
+            #.......#
+            #########
+            ",
+        );
+        assert_eq!(track.record(), Some(10));
+        assert_eq!(
+            track.histogram(&Rules::default()),
+            BTreeMap::from([(2, 1), (4, 1)])
+        );
+    }
+
+    #[test]
+    fn test_day20_off_route() {
+        let track = Track::new(
+            "
+            ########
+            #.S....#
+            ######.#
+            #E.....#
+            ########
+            ",
+        );
+        assert_eq!(track.record(), Some(11));
+        assert_eq!(
+            track.histogram(&Rules::default()),
+            BTreeMap::from([(2, 1), (4, 1), (6, 1), (8, 2)])
+        );
+        assert!(track.cheats(&Rules::default()).contains(&Cheat {
+            start: (1, 1),
+            end: (1, 3),
+            length: 2,
+            saved: 8
+        }));
+        assert!(Track::new("#####\n#S#E#\n#####")
+            .cheats(&Rules::default())
+            .is_empty());
+    }
 }
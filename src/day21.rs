@@ -1,256 +1,372 @@
-use std::cmp::Ordering;
+use ahash::AHashMap;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fmt;
 
-trait Pad {
-    fn move_to(&mut self, value: u8) -> Vec<u8>;
-}
+pub const NUMERIC: &str = "789\n456\n123\n#0A";
+pub const DIRECTIONAL: &str = "#^A\n<v>";
 
-struct NumPad {
-    x: usize,
-    y: usize,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeypadError {
+    MissingKey(u8),
+    DuplicateKey(u8),
+    ArmOnGap { level: usize },
+    InvalidPress { level: usize, key: u8 },
 }
 
-impl NumPad {
-    fn new() -> Self {
-        Self { x: 2, y: 3 }
+impl fmt::Display for KeypadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            KeypadError::MissingKey(key) => write!(f, "missing key {:?}", key as char),
+            KeypadError::DuplicateKey(key) => write!(f, "duplicate key {:?}", key as char),
+            KeypadError::ArmOnGap { level } => write!(f, "arm over a gap on pad {}", level),
+            KeypadError::InvalidPress { level, key } => {
+                write!(f, "key {:?} cannot drive pad {}", key as char, level)
+            }
+        }
     }
 }
 
-impl Pad for NumPad {
-    fn move_to(&mut self, value: u8) -> Vec<u8> {
-        let (new_x, new_y) = match value {
-            b'0' => (1, 3),
-            b'A' => (2, 3),
-            _ => (
-                ((value - b'1') % 3) as usize,
-                2 - ((value - b'1') / 3) as usize,
-            ),
-        };
+impl std::error::Error for KeypadError {}
 
-        let result = find_path(self.x, self.y, new_x, new_y, 0, 3);
-        self.x = new_x;
-        self.y = new_y;
-        result
-    }
+// A keypad given by its rows of keys, with `#` or a space marking a gap the
+// arm must never point at. Every arm starts on `A`.
+#[derive(Clone, Debug)]
+pub struct Keypad {
+    rows: Vec<Vec<u8>>,
+    positions: AHashMap<u8, (usize, usize)>,
 }
 
-struct ControlPad {
-    x: usize,
-    y: usize,
-}
+impl Keypad {
+    pub fn new(layout: &str) -> Result<Self, KeypadError> {
+        let rows = layout
+            .trim_matches('\n')
+            .lines()
+            .map(|line| line.trim_end().as_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let mut positions = AHashMap::new();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, &key) in row.iter().enumerate() {
+                if key != b'#' && key != b' ' && positions.insert(key, (x, y)).is_some() {
+                    return Err(KeypadError::DuplicateKey(key));
+                }
+            }
+        }
+        if !positions.contains_key(&b'A') {
+            return Err(KeypadError::MissingKey(b'A'));
+        }
+        Ok(Keypad { rows, positions })
+    }
 
-impl ControlPad {
-    fn new() -> Self {
-        Self { x: 2, y: 0 }
+    pub fn numeric() -> Self {
+        Keypad::new(NUMERIC).unwrap()
+    }
+
+    pub fn directional() -> Self {
+        Keypad::new(DIRECTIONAL).unwrap()
+    }
+
+    pub fn position(&self, key: u8) -> Option<(usize, usize)> {
+        self.positions.get(&key).copied()
+    }
+
+    fn is_gap(&self, x: usize, y: usize) -> bool {
+        self.rows
+            .get(y)
+            .and_then(|row| row.get(x))
+            .is_none_or(|&key| key == b'#' || key == b' ')
     }
-}
 
-impl Pad for ControlPad {
-    fn move_to(&mut self, value: u8) -> Vec<u8> {
-        let (new_x, new_y) = match value {
-            b'A' => (2, 0),
-            b'^' => (1, 0),
-            b'<' => (0, 1),
-            b'v' => (1, 1),
-            b'>' => (2, 1),
-            _ => panic!("Unexpected"),
+    fn neighbor(&self, (x, y): (usize, usize), direction: u8) -> Option<(usize, usize)> {
+        let (x, y) = match direction {
+            b'<' => (x.checked_sub(1)?, y),
+            b'>' => (x + 1, y),
+            b'^' => (x, y.checked_sub(1)?),
+            _ => (x, y + 1),
         };
-        let result = find_path(self.x, self.y, new_x, new_y, 0, 0);
-        self.x = new_x;
-        self.y = new_y;
-        result
+        (!self.is_gap(x, y)).then_some((x, y))
     }
 }
 
-fn find_path(
-    x: usize,
-    y: usize,
-    target_x: usize,
-    target_y: usize,
-    blocked_x: usize,
-    blocked_y: usize,
-) -> Vec<u8> {
-    if target_x == x || target_y == y {
-        let mut result = vec![];
-        let steps = x.abs_diff(target_x) + y.abs_diff(target_y);
-        let ch = match (x.cmp(&target_x), y.cmp(&target_y)) {
-            (Ordering::Less, _) => b'>',
-            (Ordering::Greater, _) => b'<',
-            (_, Ordering::Less) => b'v',
-            (_, Ordering::Greater) => b'^',
-            _ => b' ',
-        };
-        for _ in 0..steps {
-            result.push(ch);
-        }
-        result.push(b'A');
-        result
-    } else {
-        let x_dir = if x > target_x { b'<' } else { b'>' };
-        let y_dir = if y > target_y { b'^' } else { b'v' };
-        let mut x_first = vec![];
-        let mut y_first = vec![];
-
-        if target_x == blocked_x && y == blocked_y {
-            for _ in 0..y.abs_diff(target_y) {
-                y_first.push(y_dir);
-            }
-            for _ in 0..x.abs_diff(target_x) {
-                y_first.push(x_dir);
-            }
-            y_first.push(b'A');
-            y_first
-        } else if x == blocked_x && target_y == blocked_y {
-            for _ in 0..x.abs_diff(target_x) {
-                x_first.push(x_dir);
-            }
-            for _ in 0..y.abs_diff(target_y) {
-                x_first.push(y_dir);
+// Human presses, saturating at u128::MAX once they overflow, and the moves of
+// the cheapest way to move an arm between two keys, by pad level.
+type Memo = AHashMap<(usize, u8, u8), (u128, Vec<u8>)>;
+
+// Arm position on a pad and the last key pressed on the pad driving it, or
+// None for the position once the target key has been pressed.
+type ArmState = (Option<(usize, usize)>, u8);
+
+// Keypads from the door outwards. The first is the one the code is typed on;
+// each following one drives the arm in front of it and must therefore have
+// the direction keys. The human presses the keys that drive the last arm.
+pub struct Chain {
+    pads: Vec<Keypad>,
+}
+
+impl Chain {
+    pub fn new(pads: Vec<Keypad>) -> Result<Self, KeypadError> {
+        for pad in pads.iter().skip(1) {
+            if let Some(&key) = b"^v<>A".iter().find(|&&key| pad.position(key).is_none()) {
+                return Err(KeypadError::MissingKey(key));
             }
-            x_first.push(b'A');
-            x_first
-        } else if x < target_x {
-            for _ in 0..y.abs_diff(target_y) {
-                y_first.push(y_dir);
+        }
+        Ok(Chain { pads })
+    }
+
+    pub fn standard(robots: usize) -> Self {
+        let mut pads = vec![Keypad::numeric()];
+        pads.extend((0..robots).map(|_| Keypad::directional()));
+        Chain { pads }
+    }
+
+    // Cost of pressing `to` on pad `level` right after `from`, where the level
+    // past the last pad is the human pressing keys directly.
+    fn press_cost(&self, level: usize, from: u8, to: u8, memo: &mut Memo) -> Option<u128> {
+        if level == self.pads.len() {
+            return Some(1);
+        }
+        Some(self.best(level, from, to, memo)?.0)
+    }
+
+    // Dijkstra over the arm position on pad `level` together with the last key
+    // pressed on the pad above it. Every press on the pads above ends on `A`,
+    // so moving between two keys costs the same wherever it happens, and any
+    // route around the gaps is considered, not only the straight ones.
+    fn best(&self, level: usize, from: u8, to: u8, memo: &mut Memo) -> Option<(u128, Vec<u8>)> {
+        if let Some(best) = memo.get(&(level, from, to)) {
+            return Some(best.clone());
+        }
+        let pad = &self.pads[level];
+        let (start, target) = (pad.position(from)?, pad.position(to)?);
+        let mut costs: AHashMap<ArmState, u128> = AHashMap::new();
+        let mut previous: AHashMap<ArmState, ArmState> = AHashMap::new();
+        // Equal costs pop in push order, so ties go to horizontal moves first.
+        let mut pushed = 0usize;
+        let mut heap = BinaryHeap::new();
+        costs.insert((Some(start), b'A'), 0);
+        heap.push(Reverse((0u128, pushed, (Some(start), b'A'))));
+        while let Some(Reverse((cost, _, state))) = heap.pop() {
+            if costs[&state] < cost {
+                continue;
             }
-            for _ in 0..x.abs_diff(target_x) {
-                y_first.push(x_dir);
+            let (Some(position), last) = state else {
+                let mut moves = vec![];
+                let mut current = state;
+                while let Some(&before) = previous.get(&current) {
+                    moves.push(current.1);
+                    current = before;
+                }
+                moves.reverse();
+                memo.insert((level, from, to), (cost, moves.clone()));
+                return Some((cost, moves));
+            };
+            let mut next = vec![];
+            if position == target {
+                next.push((None, b'A'));
             }
-            y_first.push(b'A');
-            y_first
-        } else {
-            for _ in 0..x.abs_diff(target_x) {
-                x_first.push(x_dir);
+            for direction in *b"<>^v" {
+                if let Some(neighbor) = pad.neighbor(position, direction) {
+                    next.push((Some(neighbor), direction));
+                }
             }
-            for _ in 0..y.abs_diff(target_y) {
-                x_first.push(y_dir);
+            for next_state in next {
+                let Some(step) = self.press_cost(level + 1, last, next_state.1, memo) else {
+                    continue;
+                };
+                let new_cost = cost.saturating_add(step);
+                if costs
+                    .get(&next_state)
+                    .is_none_or(|&old_cost| new_cost < old_cost)
+                {
+                    costs.insert(next_state, new_cost);
+                    previous.insert(next_state, state);
+                    pushed += 1;
+                    heap.push(Reverse((new_cost, pushed, next_state)));
+                }
             }
-            x_first.push(b'A');
-            x_first
         }
+        None
     }
-}
 
-fn apply_pads(
-    ch: u8,
-    pads: &mut [ControlPad],
-    index: usize,
-    map: &Vec<(Vec<u8>, usize, usize)>,
-    map1: &mut Vec<(i64, usize, usize)>,
-) -> i64 {
-    if index == pads.len() - 1 {
-        let (value, new_x, new_y) = &map[get_cache_index(pads[index].x, pads[index].y, ch)];
-        pads[index].x = *new_x;
-        pads[index].y = *new_y;
-        return value.len() as i64;
+    fn typing_cost(&self, level: usize, keys: &[u8], memo: &mut Memo) -> Option<u128> {
+        let mut previous = b'A';
+        let mut total = 0u128;
+        for &key in keys {
+            total = total.saturating_add(self.press_cost(level, previous, key, memo)?);
+            previous = key;
+        }
+        Some(total)
     }
 
-    let cache_index = get_cache_index(pads[index].x, pads[index].y, ch);
-    let map_index = index * 25 + cache_index;
-    if map1[map_index].0 == -1 {
-        let (value, new_x, new_y) = &map[cache_index];
-        pads[index].x = *new_x;
-        pads[index].y = *new_y;
-        let mut total = 0;
-        for ch1 in value {
-            total += apply_pads(*ch1, pads, index + 1, map, map1);
+    // Fewest presses for the human to type `code` on the first pad, or None if
+    // the code cannot be typed or the count does not fit in a u128.
+    pub fn presses(&self, code: &str) -> Option<u128> {
+        self.typing_cost(0, code.as_bytes(), &mut Memo::new())
+            .filter(|&presses| presses < u128::MAX)
+    }
+
+    // Number of keys pressed on every pad, from the code itself up to the
+    // human, or None if the code cannot be typed or a length overflows.
+    pub fn level_lengths(&self, code: &str) -> Option<Vec<u128>> {
+        let mut memo = Memo::new();
+        self.typing_cost(0, code.as_bytes(), &mut memo)?;
+        let mut counts = AHashMap::new();
+        let mut previous = b'A';
+        for &key in code.as_bytes() {
+            *counts.entry((previous, key)).or_insert(0u128) += 1;
+            previous = key;
+        }
+        let mut lengths = vec![code.len() as u128];
+        for level in 0..self.pads.len() {
+            let mut next = AHashMap::new();
+            for (&(from, to), &count) in &counts {
+                let mut previous = b'A';
+                for &key in &memo[&(level, from, to)].1 {
+                    let entry = next.entry((previous, key)).or_insert(0u128);
+                    *entry = entry.checked_add(count)?;
+                    previous = key;
+                }
+            }
+            lengths.push(
+                next.values()
+                    .try_fold(0u128, |total: u128, &count| total.checked_add(count))?,
+            );
+            counts = next;
         }
+        Some(lengths)
+    }
 
-        map1[map_index] = (total, pads[index].x, pads[index].y);
+    // The presses of one shortest sequence for the human, generated lazily
+    // since deep chains make it far too long to hold.
+    pub fn stream<'a>(&'a self, code: &str) -> Option<Presses<'a>> {
+        let mut memo = Memo::new();
+        self.typing_cost(0, code.as_bytes(), &mut memo)?;
+        Some(Presses {
+            chain: self,
+            memo,
+            stack: vec![(code.as_bytes().to_vec(), 0)],
+            arms: vec![b'A'; self.pads.len()],
+        })
     }
 
-    pads[index].x = map1[map_index].1;
-    pads[index].y = map1[map_index].2;
+    pub fn sequence(&self, code: &str) -> Option<String> {
+        Some(self.stream(code)?.map(|key| key as char).collect())
+    }
 
-    map1[map_index].0
-}
+    // Applies one human press to the arms and returns the key it ends up
+    // typing on the first pad, if any.
+    fn press(&self, arms: &mut [(usize, usize)], press: u8) -> Result<Option<u8>, KeypadError> {
+        let mut key = press;
+        for level in (0..self.pads.len()).rev() {
+            let pad = &self.pads[level];
+            let (x, y) = arms[level];
+            let (new_x, new_y) = match key {
+                b'<' => (x.wrapping_sub(1), y),
+                b'>' => (x + 1, y),
+                b'^' => (x, y.wrapping_sub(1)),
+                b'v' => (x, y + 1),
+                b'A' => {
+                    key = pad.rows[y][x];
+                    continue;
+                }
+                _ => return Err(KeypadError::InvalidPress { level, key }),
+            };
+            if pad.is_gap(new_x, new_y) {
+                return Err(KeypadError::ArmOnGap { level });
+            }
+            arms[level] = (new_x, new_y);
+            return Ok(None);
+        }
+        Ok(Some(key))
+    }
 
-fn get_cache_index(x: usize, y: usize, ch: u8) -> usize {
-    ((2 - x) + 3 * (1 - y)) * 5
-        + match ch {
-            b'^' => 0,
-            b'<' => 1,
-            b'>' => 2,
-            b'v' => 3,
-            b'A' => 4,
-            _ => panic!("Unexpected"),
+    // Replays the human presses through every arm and returns what ends up
+    // typed on the first pad.
+    pub fn decode(&self, presses: &str) -> Result<String, KeypadError> {
+        let mut arms = self
+            .pads
+            .iter()
+            .map(|pad| pad.position(b'A').unwrap())
+            .collect::<Vec<_>>();
+        let mut typed = String::new();
+        for &press in presses.as_bytes() {
+            if let Some(key) = self.press(&mut arms, press)? {
+                typed.push(key as char);
+            }
         }
+        Ok(typed)
+    }
+}
+
+pub struct Presses<'a> {
+    chain: &'a Chain,
+    memo: Memo,
+    stack: Vec<(Vec<u8>, usize)>,
+    arms: Vec<u8>,
 }
 
-fn fill_map(map: &mut [(Vec<u8>, usize, usize)]) {
-    let mut pad = ControlPad::new();
-    for x in 0..3 {
-        for y in 0..2 {
-            if x == 0 && y == 0 {
+impl Iterator for Presses<'_> {
+    type Item = u8;
+
+    fn next(&mut self) -> Option<u8> {
+        loop {
+            let depth = self.stack.len() - 1;
+            let (keys, i) = self.stack.last_mut().unwrap();
+            if *i == keys.len() {
+                if depth == 0 {
+                    return None;
+                }
+                self.stack.pop();
                 continue;
             }
-            for ch in [b'<', b'>', b'^', b'v', b'A'] {
-                pad.x = x;
-                pad.y = y;
-                let value = pad.move_to(ch);
-                map[get_cache_index(x, y, ch)] = (value, pad.x, pad.y);
+            let key = keys[*i];
+            *i += 1;
+            if depth == self.chain.pads.len() {
+                return Some(key);
             }
+            let (_, moves) = self
+                .chain
+                .best(depth, self.arms[depth], key, &mut self.memo)?;
+            self.arms[depth] = key;
+            self.stack.push((moves, 0));
         }
     }
 }
 
-fn solve_code(code: &[u8], control_pads: usize, map: &Vec<(Vec<u8>, usize, usize)>) -> i64 {
-    let mut result = 0;
-    let code_value =
-        (code[0] - b'0') as i64 * 100 + (code[1] - b'0') as i64 * 10 + (code[2] - b'0') as i64;
-    let mut num_pad = NumPad::new();
-    let mut pads = vec![];
-    for _ in 0..control_pads {
-        pads.push(ControlPad::new());
-    }
-    let mut map1 = vec![(-1, 0, 0); 25 * control_pads];
-    for &ch in code {
-        for ch1 in num_pad.move_to(ch) {
-            let index = get_cache_index(pads[0].x, pads[0].y, ch1);
-            if map1[index].0 == -1 {
-                let value = apply_pads(ch1, &mut pads, 0, map, &mut map1);
-                map1[index] = (value, pads[0].x, pads[0].y);
-            }
-            result += map1[index].0;
-            pads[0].x = map1[index].1;
-            pads[0].y = map1[index].2;
-        }
-    }
-    result * code_value
+fn solve_code(code: &str, chain: &Chain) -> i64 {
+    let code_value = code
+        .bytes()
+        .filter(|key| key.is_ascii_digit())
+        .fold(0, |acc, key| acc * 10 + (key - b'0') as i64);
+    chain.presses(code).unwrap() as i64 * code_value
 }
 
-pub fn part1(input: &str) -> i64 {
-    let mut map = vec![(vec![], 0, 0); 25];
-    fill_map(&mut map);
+fn solve(input: &str, robots: usize) -> i64 {
+    let chain = Chain::standard(robots);
     input
         .lines()
-        .filter_map(|line| {
-            if !line.trim().is_empty() {
-                Some(solve_code(line.trim().as_bytes(), 2, &map))
-            } else {
-                None
-            }
-        })
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| solve_code(line, &chain))
         .sum()
 }
 
+pub fn part1(input: &str) -> i64 {
+    solve(input, 2)
+}
+
 pub fn part2(input: &str) -> i64 {
-    let mut map = vec![(vec![], 0, 0); 25];
-    fill_map(&mut map);
-    input
-        .lines()
-        .filter_map(|line| {
-            if !line.trim().is_empty() {
-                Some(solve_code(line.trim().as_bytes(), 25, &map))
-            } else {
-                None
-            }
-        })
-        .sum()
+    solve(input, 25)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ahash::AHashSet;
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use std::collections::VecDeque;
 
     const INPUT: &str = "
         029A
@@ -269,4 +385,230 @@ mod tests {
     fn test_day21_part2() {
         assert_eq!(part2(INPUT), 154115708116294);
     }
+
+    #[test]
+    fn test_day21_keypads() {
+        let pad = Keypad::new(NUMERIC).unwrap();
+        assert_eq!(pad.position(b'7'), Some((0, 0)));
+        assert_eq!(pad.position(b'A'), Some((2, 3)));
+        assert_eq!(pad.position(b'#'), None);
+        assert_eq!(pad.neighbor((1, 3), b'<'), None);
+        assert_eq!(pad.neighbor((0, 0), b'^'), None);
+        assert_eq!(pad.neighbor((2, 3), b'>'), None);
+        assert_eq!(pad.neighbor((1, 1), b'>'), Some((2, 1)));
+
+        let chain = Chain::standard(2);
+        let mut memo = Memo::new();
+        for (from, to, moves) in [
+            (b'A', b'1', "^<<A"),
+            (b'1', b'A', ">>vA"),
+            (b'A', b'9', "^^^A"),
+            (b'5', b'5', "A"),
+        ] {
+            let (_, best) = chain.best(0, from, to, &mut memo).unwrap();
+            assert_eq!(String::from_utf8(best).unwrap(), moves);
+        }
+        assert_eq!(
+            Keypad::new("12\n3").err(),
+            Some(KeypadError::MissingKey(b'A'))
+        );
+        assert_eq!(
+            Keypad::new("1A1").err(),
+            Some(KeypadError::DuplicateKey(b'1'))
+        );
+        assert_eq!(
+            Chain::new(vec![Keypad::numeric(), Keypad::numeric()]).err(),
+            Some(KeypadError::MissingKey(b'^'))
+        );
+    }
+
+    #[test]
+    fn test_day21_custom_chain() {
+        let door = Keypad::new("12A").unwrap();
+        let chain = Chain::new(vec![door.clone()]).unwrap();
+        assert_eq!(chain.presses("12A"), Some(7));
+        assert_eq!(chain.presses("3A"), None);
+        assert_eq!(Chain::new(vec![]).unwrap().presses("029A"), Some(4));
+
+        let standard = Chain::standard(2);
+        let from_text = Chain::new(vec![
+            Keypad::new("789\n456\n123\n 0A").unwrap(),
+            Keypad::new(" ^A\n<v>").unwrap(),
+            Keypad::new(DIRECTIONAL).unwrap(),
+        ])
+        .unwrap();
+        for code in ["029A", "980A", "179A", "456A", "379A"] {
+            assert_eq!(from_text.presses(code), standard.presses(code));
+        }
+        assert_eq!(standard.presses("029A"), Some(68));
+
+        // A door panel with a gap in the middle of it.
+        let ring = Chain::new(vec![
+            Keypad::new("123\n4#5\n67A").unwrap(),
+            Keypad::directional(),
+        ])
+        .unwrap();
+        assert_eq!(ring.presses("A"), Some(1));
+        assert!(ring.presses("1A").is_some());
+        // 4 and 5 sit either side of the gap, so the arm goes around it.
+        assert_eq!(ring.presses("45A"), Some(27));
+        assert_eq!(
+            ring.decode(&ring.sequence("45A").unwrap()),
+            Ok("45A".to_string())
+        );
+    }
+
+    #[test]
+    fn test_day21_sequence() {
+        let chain = Chain::standard(2);
+        for code in ["029A", "980A", "179A", "456A", "379A"] {
+            let sequence = chain.sequence(code).unwrap();
+            assert_eq!(sequence.len() as u128, chain.presses(code).unwrap());
+            assert_eq!(chain.decode(&sequence), Ok(code.to_string()));
+        }
+        assert_eq!(chain.level_lengths("029A"), Some(vec![4, 12, 28, 68]));
+        assert_eq!(Chain::standard(0).sequence("029A").unwrap(), "<A^A>^^AvvvA");
+        assert_eq!(chain.sequence("xA"), None);
+
+        assert_eq!(chain.decode("<"), Ok(String::new()));
+        assert_eq!(chain.decode("^"), Err(KeypadError::ArmOnGap { level: 2 }));
+        assert_eq!(
+            chain.decode("v<<AA"),
+            Err(KeypadError::ArmOnGap { level: 1 })
+        );
+        assert_eq!(
+            chain.decode("x"),
+            Err(KeypadError::InvalidPress {
+                level: 2,
+                key: b'x'
+            })
+        );
+        assert_eq!(
+            Chain::standard(0).decode("<<A"),
+            Err(KeypadError::ArmOnGap { level: 0 })
+        );
+    }
+
+    #[test]
+    fn test_day21_deep_stream() {
+        let chain = Chain::standard(25);
+        let lengths = chain.level_lengths("029A").unwrap();
+        assert_eq!(lengths.len(), 27);
+        assert_eq!(lengths[26], chain.presses("029A").unwrap());
+
+        let shallow = Chain::standard(3);
+        let prefix = shallow.stream("029A").unwrap().take(20).collect::<Vec<_>>();
+        let full = shallow.sequence("029A").unwrap();
+        assert_eq!(prefix, full.as_bytes()[..20]);
+        assert_eq!(chain.stream("029A").unwrap().take(10_000).count(), 10_000);
+
+        for robots in 1..=4 {
+            let chain = Chain::standard(robots);
+            let sequence = chain.sequence("029A").unwrap();
+            assert_eq!(
+                sequence.len() as u128,
+                chain.level_lengths("029A").unwrap()[robots + 1]
+            );
+            assert_eq!(chain.decode(&sequence), Ok("029A".to_string()));
+        }
+    }
+
+    // Breadth-first search over every arm position, trying all presses.
+    fn brute_force(chain: &Chain, code: &str) -> Option<u128> {
+        let arms = chain
+            .pads
+            .iter()
+            .map(|pad| pad.position(b'A').unwrap())
+            .collect::<Vec<_>>();
+        let mut seen = AHashSet::from([(arms.clone(), 0)]);
+        let mut queue = VecDeque::from([(arms, 0, 0)]);
+        while let Some((arms, typed, presses)) = queue.pop_front() {
+            if typed == code.len() {
+                return Some(presses);
+            }
+            for press in *b"<>^vA" {
+                let mut next = arms.clone();
+                let typed = match chain.press(&mut next, press) {
+                    Ok(Some(key)) if key == code.as_bytes()[typed] => typed + 1,
+                    Ok(None) => typed,
+                    _ => continue,
+                };
+                if seen.insert((next.clone(), typed)) {
+                    queue.push_back((next, typed, presses + 1));
+                }
+            }
+        }
+        None
+    }
+
+    fn random_layout(rng: &mut StdRng, keys: &[u8], width: usize, height: usize) -> String {
+        let mut cells = keys.to_vec();
+        cells.resize(width * height, b'#');
+        cells.shuffle(rng);
+        cells
+            .chunks(width)
+            .map(|row| String::from_utf8(row.to_vec()).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    #[test]
+    fn test_day21_optimal() {
+        let chain = Chain::standard(2);
+        for code in ["029A", "980A", "179A", "456A", "379A"] {
+            assert_eq!(chain.presses(code), brute_force(&chain, code));
+        }
+
+        let mut rng = StdRng::seed_from_u64(21);
+        for _ in 0..60 {
+            let height = rng.gen_range(2..4);
+            let door = random_layout(&mut rng, b"0123A", 3, height);
+            let mut pads = vec![Keypad::new(&door).unwrap()];
+            for _ in 0..rng.gen_range(1..3) {
+                let width = rng.gen_range(2..4);
+                let layout = random_layout(&mut rng, b"<>^vA", width, 6 / width);
+                pads.push(Keypad::new(&layout).unwrap());
+            }
+            let chain = Chain::new(pads).unwrap();
+            let code = (0..3)
+                .map(|_| b"0123"[rng.gen_range(0..4)] as char)
+                .chain(std::iter::once('A'))
+                .collect::<String>();
+            let presses = chain.presses(&code);
+            assert_eq!(presses, brute_force(&chain, &code), "{}\n{}", door, code);
+            let Some(presses) = presses else {
+                assert_eq!(chain.sequence(&code), None);
+                continue;
+            };
+            let sequence = chain.sequence(&code).unwrap();
+            assert_eq!(sequence.len() as u128, presses);
+            assert_eq!(chain.decode(&sequence), Ok(code));
+        }
+
+        // Both one-turn routes from A to 6 cross a gap, so the arm has to zigzag.
+        let chain = Chain::new(vec![
+            Keypad::new("A1#\n234\n#56").unwrap(),
+            Keypad::directional(),
+        ])
+        .unwrap();
+        assert_eq!(chain.presses("6A"), Some(20));
+        assert_eq!(brute_force(&chain, "6A"), Some(20));
+        assert_eq!(
+            chain.decode(&chain.sequence("6A").unwrap()),
+            Ok("6A".to_string())
+        );
+    }
+
+    #[test]
+    fn test_day21_overflow() {
+        let chain = Chain::standard(100);
+        assert_eq!(chain.presses("029A"), None);
+        assert_eq!(chain.level_lengths("029A"), None);
+        assert!(chain
+            .stream("029A")
+            .unwrap()
+            .take(100)
+            .all(|key| b"<>^vA".contains(&key)));
+        assert!(Chain::standard(60).presses("029A").is_some());
+    }
 }